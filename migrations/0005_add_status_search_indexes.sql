@@ -0,0 +1,8 @@
+-- Migration number: 0005 	 2026-10-18T00:00:00.000Z
+
+-- keyset pagination for search is ordered by (timestamp, uri), so index both columns together
+CREATE INDEX IF NOT EXISTS status_created_at_idx ON status (createdAt, uri);
+CREATE INDEX IF NOT EXISTS status_indexed_at_idx ON status (indexedAt, uri);
+
+CREATE INDEX IF NOT EXISTS status_author_created_at_idx ON status (authorDid, createdAt, uri);
+CREATE INDEX IF NOT EXISTS status_status_created_at_idx ON status (status, createdAt, uri);
@@ -0,0 +1,9 @@
+-- Migration number: 0006 	 2026-10-18T00:00:00.000Z
+
+-- the worker checks this against the version it was built for before serving requests.
+-- every later migration must bump it: UPDATE schema_version SET version = <migration number>;
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER NOT NULL
+);
+
+INSERT INTO schema_version (version) VALUES (6);
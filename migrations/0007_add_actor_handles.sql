@@ -0,0 +1,15 @@
+-- Migration number: 0007 	 2026-10-18T00:00:00.000Z
+
+-- handles of status authors, resolved when statuses are written so pages can be rendered
+-- without touching the resolver. verifiedAt is when the handle was last checked against the
+-- DID document, stale rows are refreshed by the scheduled worker.
+CREATE TABLE IF NOT EXISTS actor_handle (
+    did TEXT PRIMARY KEY,
+    handle TEXT,
+    handleVerified INTEGER NOT NULL DEFAULT FALSE,
+    verifiedAt TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS actor_handle_verified_at ON actor_handle (verifiedAt);
+
+UPDATE schema_version SET version = 7;
@@ -0,0 +1,9 @@
+-- Migration number: 0008 	 2026-10-18T00:00:00.000Z
+
+-- optional fields from the extended xyz.statusphere.status lexicon. statuses past expiresAt
+-- stay in the index but are left out of the feed
+ALTER TABLE status ADD COLUMN text TEXT;
+ALTER TABLE status ADD COLUMN expiresAt TEXT;
+ALTER TABLE status ADD COLUMN url TEXT;
+
+UPDATE schema_version SET version = 8;
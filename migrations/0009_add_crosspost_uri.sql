@@ -0,0 +1,7 @@
+-- Migration number: 0009 	 2026-10-18T00:00:00.000Z
+
+-- at:// uri of the app.bsky.feed.post shared alongside a status, so deleting the status can
+-- take the post with it
+ALTER TABLE status ADD COLUMN crosspostUri TEXT;
+
+UPDATE schema_version SET version = 9;
@@ -0,0 +1,16 @@
+-- Migration number: 0010 	 2026-10-19T00:00:00.000Z
+
+-- search compares and orders timestamps with julianday() so text forms of differing precision
+-- sort correctly, which only the expression can be indexed for. indexedAt keeps its plain
+-- index, the recent statuses query orders by it directly
+DROP INDEX IF EXISTS status_created_at_idx;
+DROP INDEX IF EXISTS status_author_created_at_idx;
+DROP INDEX IF EXISTS status_status_created_at_idx;
+
+CREATE INDEX IF NOT EXISTS status_created_at_date_idx ON status (julianday(createdAt), uri);
+CREATE INDEX IF NOT EXISTS status_indexed_at_date_idx ON status (julianday(indexedAt), uri);
+
+CREATE INDEX IF NOT EXISTS status_author_created_at_date_idx ON status (authorDid, julianday(createdAt), uri);
+CREATE INDEX IF NOT EXISTS status_status_created_at_date_idx ON status (status, julianday(createdAt), uri);
+
+UPDATE schema_version SET version = 10;
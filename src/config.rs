@@ -0,0 +1,184 @@
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context as _};
+use atrium_api::types::string::Did;
+use http::Uri;
+use worker::Env;
+
+use crate::durable_object::rate_limiter::RateLimit;
+
+// atproto only accepts loopback IPs (not `localhost`) in development client redirect URIs
+const DEV_PUBLIC_URL: &str = "http://127.0.0.1:8787";
+// what wrangler.toml ships with, so a deploy that never set its own fails loudly
+const PLACEHOLDER_PUBLIC_URL: &str = "https://statusphere.example.com";
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+/// How status changes are written to a user's repo
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatusRecordMode {
+    /// a new record per status, the repo keeps the full history
+    #[default]
+    Append,
+    /// one record at a fixed rkey, overwritten on every change
+    Single,
+}
+
+/// Token buckets for the endpoints that write to PDSes or start logins. None turns a limit off.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub status_per_did: Option<RateLimit>,
+    pub status_per_ip: Option<RateLimit>,
+    pub login_per_ip: Option<RateLimit>,
+}
+
+impl RateLimits {
+    fn from_env(env: &Env) -> anyhow::Result<Self> {
+        Ok(Self {
+            status_per_did: rate_limit_var(env, "STATUS_RATE_LIMIT_PER_DID", "10/60")?,
+            status_per_ip: rate_limit_var(env, "STATUS_RATE_LIMIT_PER_IP", "30/60")?,
+            login_per_ip: rate_limit_var(env, "LOGIN_RATE_LIMIT_PER_IP", "10/300")?,
+        })
+    }
+}
+
+// unset falls back to the default, set but empty disables the limit
+fn rate_limit_var(env: &Env, name: &str, default: &str) -> anyhow::Result<Option<RateLimit>> {
+    let spec = match env.var(name) {
+        Ok(spec) => spec.to_string(),
+        Err(_) => default.to_string(),
+    };
+    if spec.trim().is_empty() {
+        return Ok(None);
+    }
+
+    spec.parse().map(Some).map_err(|e| anyhow!("{name}: {e}"))
+}
+
+/// Where the app is served from, read from env vars and validated once per isolate
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// canonical origin, eg `https://statusphere.example`, without a trailing slash. OAuth
+    /// client IDs and redirect URIs are built from this, never from the request
+    pub public_url: String,
+    /// run as a localhost OAuth public client, for `wrangler dev`
+    pub dev_mode: bool,
+    pub status_record_mode: StatusRecordMode,
+    pub rate_limits: RateLimits,
+    /// accounts allowed into `/admin/*` with their normal login session
+    pub admin_dids: Vec<Did>,
+    // authority (host and port) of `public_url`
+    canonical_host: String,
+    // extra hosts served as is, eg a workers.dev route used for health checks
+    allowed_hosts: Vec<String>,
+}
+
+impl AppConfig {
+    /// Loads the config on first use. Errors aren't cached, so a fixed var takes effect on the
+    /// next request.
+    pub fn get(env: &Env) -> anyhow::Result<&'static Self> {
+        if let Some(config) = CONFIG.get() {
+            return Ok(config);
+        }
+
+        let config = Self::from_env(env)?;
+        Ok(CONFIG.get_or_init(|| config))
+    }
+
+    /// Reads `PUBLIC_URL`, `DEV_MODE`, `ALLOWED_HOSTS`, `STATUS_RECORD_MODE`, `ADMIN_DIDS` and
+    /// the `*_RATE_LIMIT_*` vars. `PUBLIC_URL` is required outside
+    /// of dev mode, where it defaults to `http://127.0.0.1:8787`.
+    fn from_env(env: &Env) -> anyhow::Result<Self> {
+        let dev_mode = env.var("DEV_MODE").is_ok_and(|v| v.to_string() == "true");
+
+        let public_url = match env.var("PUBLIC_URL") {
+            Ok(url) => url.to_string(),
+            Err(_) if dev_mode => DEV_PUBLIC_URL.to_string(),
+            Err(_) => bail!("PUBLIC_URL must be set outside of dev mode"),
+        };
+        let public_url = public_url.trim_end_matches('/').to_string();
+        if public_url == PLACEHOLDER_PUBLIC_URL {
+            bail!("PUBLIC_URL is still the placeholder {PLACEHOLDER_PUBLIC_URL}, set it to the origin this worker is served from in wrangler.toml");
+        }
+
+        let uri: Uri = public_url
+            .parse()
+            .with_context(|| format!("invalid PUBLIC_URL {public_url}"))?;
+        let canonical_host = uri
+            .authority()
+            .ok_or_else(|| anyhow!("PUBLIC_URL {public_url} has no host"))?
+            .to_string();
+        if uri.path_and_query().is_some_and(|p| p.as_str() != "/") {
+            bail!("PUBLIC_URL {public_url} must be an origin, without a path");
+        }
+
+        match (uri.scheme_str(), dev_mode) {
+            (Some("https"), false) => {}
+            (Some("http"), true) => {
+                if !matches!(uri.host(), Some("127.0.0.1" | "[::1]")) {
+                    bail!("dev mode PUBLIC_URL must be on 127.0.0.1 or [::1], not {public_url}");
+                }
+            }
+            (Some("https"), true) => bail!("dev mode needs an http loopback PUBLIC_URL"),
+            _ => bail!("PUBLIC_URL {public_url} must use https"),
+        }
+
+        let allowed_hosts = match env.var("ALLOWED_HOSTS") {
+            Ok(hosts) => hosts
+                .to_string()
+                .split(',')
+                .map(|h| h.trim().to_ascii_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let status_record_mode = match env.var("STATUS_RECORD_MODE") {
+            Err(_) => StatusRecordMode::default(),
+            Ok(mode) => match mode.to_string().as_str() {
+                "" | "append" => StatusRecordMode::Append,
+                "single" => StatusRecordMode::Single,
+                other => {
+                    bail!("STATUS_RECORD_MODE must be \"append\" or \"single\", not {other:?}")
+                }
+            },
+        };
+
+        let admin_dids = match env.var("ADMIN_DIDS") {
+            Ok(dids) => dids
+                .to_string()
+                .split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(|d| {
+                    Did::new(d.to_string())
+                        .map_err(|e| anyhow!("invalid ADMIN_DIDS entry {d:?}: {e}"))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Err(_) => Vec::new(),
+        };
+
+        Ok(Self {
+            public_url,
+            dev_mode,
+            status_record_mode,
+            rate_limits: RateLimits::from_env(env)?,
+            admin_dids,
+            canonical_host: canonical_host.to_ascii_lowercase(),
+            allowed_hosts,
+        })
+    }
+
+    /// Whether a request for `host` (an authority, ie with the port if any) is served rather
+    /// than redirected to the canonical URL
+    pub fn serves_host(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        host == self.canonical_host || self.allowed_hosts.contains(&host)
+    }
+
+    /// The same path and query on the canonical origin
+    pub fn canonical_url(&self, uri: &Uri) -> String {
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+        format!("{}{path}", self.public_url)
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use crate::types::errors::AppError;
-use crate::types::status::StatusFromDb;
+use crate::types::status::{StatusDeleted, StatusFromDb};
 use anyhow::{anyhow, Context as _};
 use http::Request;
 use worker::send::SendWrapper;
@@ -38,11 +38,26 @@ impl MessageBroker {
 
     pub async fn broadcast(&self, status: StatusFromDb) -> anyhow::Result<()> {
         console_log!("broadcast status");
+        self.post("https://stub.com/broadcast_status", &status)
+            .await
+    }
+
+    /// Tells connected clients a status is gone
+    pub async fn broadcast_deletion(&self, uri: &str) -> anyhow::Result<()> {
+        console_log!("broadcast status deletion");
+        let deleted = StatusDeleted {
+            deleted: uri.to_string(),
+        };
+        self.post("https://stub.com/broadcast_deletion", &deleted)
+            .await
+    }
+
+    async fn post(&self, uri: &str, body: &impl serde::Serialize) -> anyhow::Result<()> {
         let req = Request::builder()
             .method("POST")
-            .uri("https://stub.com/broadcast_status")
+            .uri(uri)
             .header("Content-Type", "application/json")
-            .body(serde_json::to_string(&status).context("convert to json")?)
+            .body(serde_json::to_string(body).context("convert to json")?)
             .context("building request")?;
 
         let req = request_to_wasm(req).context("building req")?;
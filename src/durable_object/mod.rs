@@ -1,2 +1,3 @@
 pub mod client;
+pub mod rate_limiter;
 pub mod server;
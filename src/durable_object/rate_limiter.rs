@@ -0,0 +1,180 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _};
+use serde::{Deserialize, Serialize};
+use worker::send::SendWrapper;
+use worker::{
+    durable_object, wasm_bindgen, wasm_bindgen_futures, Env, Method, ObjectNamespace, State,
+};
+
+/// A token bucket: up to `capacity` requests at once, refilling completely over `period`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    fn refill_per_ms(&self) -> f64 {
+        self.capacity as f64 / self.period.as_millis().max(1) as f64
+    }
+}
+
+impl std::str::FromStr for RateLimit {
+    type Err = String;
+
+    /// `<capacity>/<seconds>`, eg `10/60` for ten requests a minute
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (capacity, secs) = s
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| format!("rate limit {s:?} should look like 10/60"))?;
+        let capacity: u32 = capacity
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rate limit capacity in {s:?}"))?;
+        let secs: u64 = secs
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rate limit period in {s:?}"))?;
+        if capacity == 0 || secs == 0 {
+            return Err(format!("rate limit {s:?} must allow at least one request"));
+        }
+
+        Ok(Self {
+            capacity,
+            period: Duration::from_secs(secs),
+        })
+    }
+}
+
+/// Answer from the limiter for one request
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// when a token will next be available, zero if allowed
+    pub retry_after: Duration,
+}
+
+/// Frontend side handle on the rate limiter objects, one object per limited key
+#[derive(Clone)]
+pub struct RateLimiter {
+    ns: Arc<SendWrapper<ObjectNamespace>>,
+}
+
+impl RateLimiter {
+    pub fn from_namespace(ns: ObjectNamespace) -> Self {
+        Self {
+            ns: Arc::new(SendWrapper(ns)),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket. Keys should be namespaced by what they limit, eg
+    /// `status:did:plc:...`, since each key gets its own bucket
+    pub async fn take(&self, key: &str, limit: RateLimit) -> anyhow::Result<RateLimitDecision> {
+        let stub = self
+            .ns
+            .id_from_name(key)
+            .and_then(|id| id.get_stub())
+            .map_err(|e| anyhow!("rate limiter stub for {key}: {e}"))?;
+
+        let mut init = worker::RequestInit::new();
+        init.with_method(Method::Post).with_body(Some(
+            serde_json::to_string(&limit)
+                .context("convert to json")?
+                .into(),
+        ));
+        let req = worker::Request::new_with_init("https://stub.com/take", &init)
+            .map_err(|e| anyhow!("building rate limit request: {e}"))?;
+
+        let mut resp = stub
+            .fetch_with_request(req)
+            .await
+            .map_err(|e| anyhow!("rate limiter fetch: {e}"))?;
+
+        resp.json()
+            .await
+            .map_err(|e| anyhow!("rate limiter response: {e}"))
+    }
+}
+
+// what a bucket persists between requests
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Bucket {
+    tokens: f64,
+    updated_ms: u64,
+}
+
+/// One token bucket. Durable objects handle one request at a time, so the read-modify-write
+/// here can't race.
+#[durable_object]
+pub struct RateLimitBucket {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for RateLimitBucket {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: worker::Request) -> worker::Result<worker::Response> {
+        match req.url()?.path() {
+            "/take" if req.method() == Method::Post => {
+                let limit: RateLimit = req.json().await?;
+                let decision = self.take(limit).await?;
+                worker::Response::from_json(&decision)
+            }
+            _ => worker::Response::error("unsupported method/endpoint", 400),
+        }
+    }
+
+    // a bucket left alone for a whole period is full again, same as one that doesn't exist
+    async fn alarm(&mut self) -> worker::Result<worker::Response> {
+        self.state.storage().delete_all().await?;
+        worker::Response::empty()
+    }
+}
+
+impl RateLimitBucket {
+    async fn take(&mut self, limit: RateLimit) -> worker::Result<RateLimitDecision> {
+        let mut storage = self.state.storage();
+        let now = worker::Date::now().as_millis();
+        let capacity = limit.capacity as f64;
+
+        let tokens = match storage.get::<Bucket>("bucket").await {
+            Ok(bucket) => {
+                let elapsed = now.saturating_sub(bucket.updated_ms) as f64;
+                (bucket.tokens + elapsed * limit.refill_per_ms()).min(capacity)
+            }
+            Err(_) => capacity,
+        };
+
+        let decision = if tokens >= 1.0 {
+            storage
+                .put(
+                    "bucket",
+                    Bucket {
+                        tokens: tokens - 1.0,
+                        updated_ms: now,
+                    },
+                )
+                .await?;
+            storage.set_alarm(limit.period).await?;
+
+            RateLimitDecision {
+                allowed: true,
+                retry_after: Duration::ZERO,
+            }
+        } else {
+            let wait_ms = ((1.0 - tokens) / limit.refill_per_ms()).ceil() as u64;
+            RateLimitDecision {
+                allowed: false,
+                retry_after: Duration::from_millis(wait_ms),
+            }
+        };
+
+        Ok(decision)
+    }
+}
@@ -1,10 +1,6 @@
-use crate::services::resolvers;
-use crate::services::resolvers::did_resolver;
-use crate::types::status::StatusFromDb;
 use crate::types::status::StatusWithHandle;
-use atrium_oauth::DefaultHttpClient;
+use crate::types::status::{StatusDeleted, StatusFromDb};
 use serde_json::json;
-use std::sync::Arc;
 use worker::console_debug;
 use worker::Method;
 use worker::{
@@ -15,20 +11,12 @@ use worker::{
 #[durable_object]
 pub struct MsgBroker {
     state: State,
-    did_resolver: resolvers::DidResolver,
 }
 
 #[durable_object]
 impl DurableObject for MsgBroker {
-    fn new(state: State, env: Env) -> Self {
-        let kv = Arc::new(env.kv("KV").expect("invalid KV binding"));
-
-        let did_resolver = did_resolver(&Arc::new(DefaultHttpClient::default()), &kv);
-
-        Self {
-            state,
-            did_resolver,
-        }
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
     }
 
     async fn websocket_message(
@@ -55,6 +43,7 @@ impl DurableObject for MsgBroker {
         // the communication here is all between two closely coupled workers so
         // we can abandon the axum routing used in the frontend-facing worker
         // which must support content encodings, work with headers, etc
+        #[allow(clippy::collapsible_match)]
         match req.url()?.path() {
             "/subscribe_websocket" => {
                 if req.method() == Method::Get {
@@ -68,6 +57,13 @@ impl DurableObject for MsgBroker {
                     return worker::Response::empty();
                 }
             }
+            "/broadcast_deletion" => {
+                if req.method() == Method::Post {
+                    let deleted = req.json().await?;
+                    self.broadcast_deletion(deleted).await?;
+                    return worker::Response::empty();
+                }
+            }
             _ => {}
         }
 
@@ -78,12 +74,8 @@ impl DurableObject for MsgBroker {
 impl MsgBroker {
     #[worker::send]
     async fn broadcast(&mut self, status: StatusFromDb) -> worker::Result<()> {
-        let mut status = StatusWithHandle::from(status);
-
-        status.handle = self
-            .did_resolver
-            .resolve_handle_for_did(&status.author_did)
-            .await;
+        // handles are stored with the status, so nothing to resolve here
+        let status = StatusWithHandle::from(status);
 
         for ws in self.state.get_websockets() {
             if let Err(e) = ws.send(&status) {
@@ -94,6 +86,16 @@ impl MsgBroker {
         Ok(())
     }
 
+    async fn broadcast_deletion(&mut self, deleted: StatusDeleted) -> worker::Result<()> {
+        for ws in self.state.get_websockets() {
+            if let Err(e) = ws.send(&deleted) {
+                console_log!("error {e} on websocket send");
+            }
+        }
+
+        Ok(())
+    }
+
     async fn subscribe_websocket(&mut self) -> worker::Result<worker::Response> {
         console_log!("subscriber websocket server");
 
@@ -0,0 +1,58 @@
+use atrium_api::types::string::Did;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_extra::TypedHeader;
+use headers::authorization::Bearer;
+use headers::Authorization;
+use tower_sessions::Session;
+
+use crate::types::errors::AppError;
+
+use super::state::AppState;
+
+/// Compares a presented secret against the expected one without short-circuiting on the
+/// first mismatched byte, so response timing doesn't leak how much of a guess was right
+pub fn secret_matches(expected: &str, presented: &str) -> bool {
+    let (expected, presented) = (expected.as_bytes(), presented.as_bytes());
+
+    if expected.len() != presented.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(presented)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Guards `/admin/*`. Lets in a bearer token matching the `ADMIN_TOKEN` secret, or a logged in
+/// user whose DID is in `ADMIN_DIDS`. With neither configured nothing gets in.
+#[worker::send]
+pub async fn require_admin(
+    State(AppState {
+        config,
+        admin_token,
+        ..
+    }): State<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    // a wrong token is refused outright, it doesn't fall back to the session
+    if let Some(TypedHeader(auth)) = bearer {
+        return match admin_token {
+            Some(expected) if secret_matches(&expected, auth.token()) => {
+                Ok(next.run(request).await)
+            }
+            _ => Err(AppError::NoAdminAuth),
+        };
+    }
+
+    match session.get::<Did>("did").await? {
+        Some(did) if config.admin_dids.contains(&did) => Ok(next.run(request).await),
+        _ => Err(AppError::NoAdminAuth),
+    }
+}
@@ -1,27 +1,54 @@
+use crate::config::AppConfig;
 use crate::frontend_worker::state::ScheduledEventState;
+use crate::services::agent::{Agent, CurrentStatus};
+use crate::services::crosspost;
+use crate::services::export::{export_body, ExportFormat};
+use crate::services::feeds::{Feed, FeedFormat};
+use crate::services::handles;
+use crate::services::idempotency::IdempotencyKey;
 use crate::services::jetstream::handle_jetstream_event;
+use crate::services::oauth::LoginIdentifier;
+use crate::services::resolvers::HandleResolver;
+use crate::storage::db::{SchemaStatus, StatusDb};
+use crate::storage::query::{SortOrder, StatusQuery, TimeField};
+use crate::types::errors::LoginError;
 use crate::types::jetstream;
 use crate::types::lexicons::xyz;
-use crate::types::status::STATUS_OPTIONS;
+use crate::types::status::{check_status_limits, STATUS_OPTIONS};
+use crate::types::templates::{ProfileTemplate, ReconsentTemplate, SessionView, SessionsTemplate};
 use crate::{types::errors::AppError, types::templates::HomeTemplate};
 use crate::{
-    types::status::{Status, StatusWithHandle},
+    types::status::{Status, StatusDetails, StatusFromDb, StatusWithHandle},
     types::templates::Profile,
 };
 use anyhow::Context as _;
-use atrium_api::types::string::Handle;
+use atrium_api::types::string::{Did, Handle, RecordKey};
+use atrium_api::types::Collection as _;
+use atrium_common::resolver::Resolver as _;
 use atrium_oauth::{CallbackParams, OAuthClientMetadata};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     response::Redirect,
 };
 use axum::{Form, Json};
+use axum_extra::typed_header::TypedHeaderRejection;
 use axum_extra::TypedHeader;
-use headers::{Authorization, Upgrade};
+use chrono::{DateTime, Utc};
+use headers::authorization::Bearer;
+use headers::{
+    Authorization, CacheControl, HeaderMapExt as _, IfModifiedSince, LastModified, Upgrade,
+    UserAgent,
+};
+use jose_jwk::JwkSet;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, UNIX_EPOCH};
 use tower_sessions::Session;
-use worker::{console_log, HttpResponse};
+use worker::{console_error, console_log, HttpResponse};
 
+use super::auth::secret_matches;
+use super::rate_limit;
 use super::state::AppState;
 
 #[worker::send]
@@ -31,37 +58,221 @@ pub async fn client_metadata(
     Json(oauth.client_metadata())
 }
 
+/// Public keys for verifying our private_key_jwt client assertions
+#[worker::send]
+pub async fn jwks(State(AppState { oauth, .. }): State<AppState>) -> Json<JwkSet> {
+    Json(oauth.jwks())
+}
+
+/// What the authorization server redirects back with: a code on success, an error otherwise
+/// (https://datatracker.ietf.org/doc/html/rfc6749#section-4.1.2.1)
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    iss: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
 /// OAuth callback endpoint to complete session creation
 #[worker::send]
 pub async fn oauth_callback(
-    Query(params): Query<CallbackParams>,
-    State(AppState { oauth, .. }): State<AppState>,
+    Query(params): Query<CallbackQuery>,
+    State(AppState {
+        oauth, sessions, ..
+    }): State<AppState>,
     session: tower_sessions::Session,
+    user_agent: Option<TypedHeader<UserAgent>>,
 ) -> Result<Redirect, AppError> {
-    let did = oauth.callback(params).await?;
-    session.insert("did", did).await?;
+    if let Some(code) = params.error {
+        return Err(match code.as_str() {
+            "access_denied" => LoginError::Denied,
+            _ => LoginError::AuthorizationServer {
+                code,
+                description: params.error_description,
+            },
+        }
+        .into());
+    }
+    let Some(code) = params.code else {
+        return Err(LoginError::Malformed("missing `code` parameter".to_string()).into());
+    };
+
+    let did = oauth
+        .callback(CallbackParams {
+            code,
+            state: params.state,
+            iss: params.iss,
+        })
+        .await?;
+
+    // new session ID on login, so an ID planted in the browser beforehand is useless
+    session.cycle_id().await?;
+    session.insert("did", &did).await?;
+    // save now rather than on the way out so we know the new ID
+    session.save().await?;
+
+    if let Some(id) = session.id() {
+        sessions
+            .register(&did, id, user_agent.map(|ua| ua.as_str().to_string()))
+            .await
+            .context("registering session")?;
+    }
+
     Ok(Redirect::to("/"))
 }
 
 /// Log out of current session
-pub async fn logout(session: Session) -> Result<Redirect, AppError> {
+#[worker::send]
+pub async fn logout(
+    State(AppState {
+        oauth, sessions, ..
+    }): State<AppState>,
+    session: Session,
+) -> Result<Redirect, AppError> {
+    if let (Some(did), Some(id)) = (session.get::<Did>("did").await?, session.id()) {
+        let remaining = sessions.forget(&did, id).await.context("forget session")?;
+        // the oauth session is shared by all of a user's browser sessions
+        if remaining == 0 {
+            oauth.sign_out(&did).await?;
+        }
+    }
+
     session.flush().await.context("session delete")?;
 
     Ok(Redirect::to("/"))
 }
 
+/// List the current user's active sessions
+#[worker::send]
+pub async fn list_sessions(
+    State(AppState { sessions, .. }): State<AppState>,
+    session: Session,
+) -> Result<SessionsTemplate, AppError> {
+    let did: Did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+    let current = session.id();
+
+    let sessions = sessions
+        .list(&did)
+        .await
+        .context("listing sessions")?
+        .into_iter()
+        .map(|s| SessionView {
+            current: current.is_some_and(|id| s.is(&id)),
+            handle: s.handle,
+            created_at: s.created_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            last_seen: s.last_seen.format("%Y-%m-%d %H:%M UTC").to_string(),
+            user_agent: s.user_agent.unwrap_or_else(|| "unknown device".to_string()),
+        })
+        .collect();
+
+    Ok(SessionsTemplate { sessions })
+}
+
+/// Revoke one of the current user's sessions
+#[worker::send]
+pub async fn revoke_session(
+    State(AppState {
+        oauth, sessions, ..
+    }): State<AppState>,
+    session: Session,
+    Path(handle): Path<String>,
+) -> Result<Redirect, AppError> {
+    let did: Did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+
+    let revoking_current = match session.id() {
+        Some(id) => sessions
+            .list(&did)
+            .await
+            .context("listing sessions")?
+            .iter()
+            .any(|s| s.handle == handle && s.is(&id)),
+        None => false,
+    };
+
+    let remaining = sessions
+        .revoke(&did, &handle)
+        .await
+        .context("revoking session")?;
+    if remaining == 0 {
+        oauth.sign_out(&did).await?;
+    }
+
+    // revoking the session we're in deleted it from the store out from under us
+    if revoking_current {
+        session.flush().await?;
+        return Ok(Redirect::to("/"));
+    }
+
+    Ok(Redirect::to("/sessions"))
+}
+
+/// Revoke every session for the current user, including this one, and their OAuth session
+#[worker::send]
+pub async fn revoke_all_sessions(
+    State(AppState {
+        oauth, sessions, ..
+    }): State<AppState>,
+    session: Session,
+) -> Result<Redirect, AppError> {
+    let did: Did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+
+    sessions
+        .revoke_all(&did)
+        .await
+        .context("revoking sessions")?;
+    oauth.sign_out(&did).await?;
+    session.flush().await?;
+
+    Ok(Redirect::to("/"))
+}
+
+/// Login form input: a handle, a DID, or a PDS/entryway URL
 #[derive(Deserialize)]
 pub struct LoginForm {
-    handle: Handle,
+    identifier: String,
 }
 
 /// Establish a session via oauth
 #[worker::send]
 pub async fn login(
+    State(AppState {
+        config,
+        oauth,
+        rate_limiter,
+        ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    Form(LoginForm { identifier }): Form<LoginForm>,
+) -> Result<Redirect, AppError> {
+    if let Some(ip) = rate_limit::client_ip(&headers) {
+        let key = format!("login:ip:{ip}");
+        rate_limit::enforce(&rate_limiter, &key, config.rate_limits.login_per_ip).await?;
+    }
+
+    let parsed: LoginIdentifier =
+        identifier
+            .parse()
+            .map_err(|reason| LoginError::InvalidIdentifier {
+                input: identifier.clone(),
+                reason,
+            })?;
+
+    Ok(Redirect::to(&oauth.auth_redirect_url(&parsed).await?))
+}
+
+/// Send a logged in user back to their authorization server to grant missing scopes
+#[worker::send]
+pub async fn reconsent(
     State(AppState { oauth, .. }): State<AppState>,
-    Form(LoginForm { handle }): Form<LoginForm>,
+    session: Session,
 ) -> Result<Redirect, AppError> {
-    Ok(Redirect::to(&oauth.auth_redirect_url(handle).await?))
+    let Some(did) = session.get::<Did>("did").await? else {
+        return Ok(Redirect::to("/"));
+    };
+
+    Ok(Redirect::to(&oauth.reconsent_redirect_url(&did).await?))
 }
 
 /// Render the home page
@@ -70,22 +281,16 @@ pub async fn home(
     State(AppState {
         oauth,
         status_db,
-        did_resolver,
+        sessions,
         ..
     }): State<AppState>,
     session: tower_sessions::Session,
-) -> Result<HomeTemplate, AppError> {
-    // Fetch recent statuses for template seeding (no handle resolution for now)
+) -> Result<Response, AppError> {
+    // Fetch recent statuses for template seeding, handles come stored alongside them
     let recent_statuses = match status_db.load_latest_statuses(20).await {
         Ok(statuses) => {
-            let mut statuses_with_handles = Vec::new();
-            for s in statuses.into_iter() {
-                let mut status = crate::types::status::StatusWithHandle::from(s);
-                status.handle = did_resolver
-                    .resolve_handle_for_did(&status.author_did)
-                    .await;
-                statuses_with_handles.push(status);
-            }
+            let mut statuses_with_handles: Vec<StatusWithHandle> =
+                statuses.into_iter().map(StatusWithHandle::from).collect();
             // enforce chronological ordering
             statuses_with_handles.sort_by_key(|s| s.created_at);
             statuses_with_handles.reverse();
@@ -104,12 +309,18 @@ pub async fn home(
             status_options: &STATUS_OPTIONS,
             profile: None,
             my_status: None,
+            my_status_uri: None,
             recent_statuses,
-        });
+        }
+        .into_response());
     };
 
     let agent = match oauth.restore_session(&did).await {
         Ok(agent) => agent,
+        // keep the session, re-consenting needs to know who they are
+        Err(AppError::InsufficientScope(missing)) => {
+            return Ok(ReconsentTemplate { missing }.into_response());
+        }
         Err(err) => {
             // Destroys the system or you're in a loop
             session.flush().await?;
@@ -117,6 +328,12 @@ pub async fn home(
         }
     };
 
+    if let Some(id) = session.id() {
+        if let Err(e) = sessions.touch(&did, id).await {
+            console_log!("error updating session last seen: {}", e);
+        }
+    }
+
     let current_status = agent.current_status().await?;
 
     let profile = match agent.bsky_profile().await {
@@ -127,12 +344,19 @@ pub async fn home(
                 status_options: &STATUS_OPTIONS,
                 profile: None,
                 my_status: None,
+                my_status_uri: None,
                 recent_statuses,
-            });
+            }
+            .into_response());
         }
         Err(e) => return Err(e),
     };
 
+    let (my_status_uri, my_status) = match current_status {
+        Some(CurrentStatus { uri, record }) => (Some(uri), Some(record)),
+        None => (None, None),
+    };
+
     let username = match profile.display_name {
         Some(username) => username,
         // we could also resolve this via com.api.atproto.identity
@@ -145,34 +369,82 @@ pub async fn home(
             did: did.to_string(),
             display_name: Some(username),
         }),
-        my_status: current_status,
+        my_status,
+        my_status_uri,
         recent_statuses,
-    })
+    }
+    .into_response())
 }
 
 /// Post body for changing your status
 #[derive(Serialize, Deserialize, Clone)]
 pub struct StatusForm {
     status: String,
+    #[serde(flatten)]
+    details: StatusDetails,
+    /// also share the status as a Bluesky post
+    #[serde(default)]
+    crosspost: bool,
+    /// when the client first tried this write, required with an `Idempotency-Key` and sent
+    /// unchanged on retries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attempted_at: Option<DateTime<Utc>>,
 }
 
 /// Publish a status record
 #[worker::send]
 pub async fn status(
     State(AppState {
+        config,
         oauth,
         status_db,
         durable_object,
-        did_resolver,
+        rate_limiter,
+        actor_handle_resolver,
+        idempotency,
+        ..
     }): State<AppState>,
     session: Session,
+    headers: HeaderMap,
     form: Json<StatusForm>,
 ) -> Result<Json<StatusWithHandle>, AppError> {
     console_log!("status handler");
-    let did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+    let did: Did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+
+    // a retry of a write that already went through gets the original response, and doesn't
+    // count against the rate limit
+    let idempotency_key = IdempotencyKey::from_headers(&headers, &did)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(original) = key.replay(&idempotency, &form.0).await? {
+            return Ok(Json(original));
+        }
+    }
+
+    // a request that can never succeed shouldn't use up rate limit tokens. the status is held
+    // to the same limits ingest applies to everyone else's records
+    check_status_limits(&form.status).map_err(AppError::BadRequest)?;
+    let details = form
+        .details
+        .clone()
+        .validated()
+        .map_err(AppError::BadRequest)?;
+    let rkey = match &idempotency_key {
+        Some(key) => Some(key.record_key(form.attempted_at)?),
+        None => None,
+    };
+
+    let limits = &config.rate_limits;
+    if let Some(ip) = rate_limit::client_ip(&headers) {
+        let key = format!("status:ip:{ip}");
+        rate_limit::enforce(&rate_limiter, &key, limits.status_per_ip).await?;
+    }
+    let key = format!("status:{}", did.as_str());
+    rate_limit::enforce(&rate_limiter, &key, limits.status_per_did).await?;
 
     let agent = match oauth.restore_session(&did).await {
         Ok(agent) => agent,
+        // a 403 the page handles by sending the user through re-consent
+        Err(err @ AppError::InsufficientScope(_)) => return Err(err),
         Err(err) => {
             // Destroys the system or you're in a loop
             session.flush().await?;
@@ -180,23 +452,458 @@ pub async fn status(
         }
     };
 
-    let uri = agent.create_status(form.status.clone()).await?.uri;
+    let uri = agent
+        .create_status(form.status.clone(), &details, rkey)
+        .await?;
+
+    // the record is already in the user's repo, failing now would only invite a retry
+    if let Err(e) = handles::ensure_actor_handle(&status_db, &actor_handle_resolver, &did).await {
+        console_error!("storing handle for {} failed: {}", did.as_str(), e);
+    }
 
-    let status = Status::new(uri, did, form.status.clone());
-    let status_from_db = status_db
+    let status = Status::new(uri, did, form.status.clone(), details);
+    let mut status_from_db = status_db
         .save_optimistic(&status)
         .await
         .context("saving status")?;
 
+    if form.crosspost {
+        // the status itself is already published, so a failed share shouldn't fail the request
+        match crosspost_status(&agent, config, &status, &status_from_db).await {
+            Ok(post_uri) => {
+                status_from_db = status_db
+                    .save_crosspost_uri(&status.uri, &post_uri)
+                    .await
+                    .context("saving crosspost uri")?;
+            }
+            Err(e) => console_error!("cross-posting {} failed: {}", status.uri, e),
+        }
+    }
+
     // Broadcast to WebSocket clients
     durable_object.broadcast(status_from_db.clone()).await?;
 
     // Convert to StatusWithHandle and return as JSON
-    let mut status_with_handle = StatusWithHandle::from(status_from_db);
-    status_with_handle.handle = did_resolver
-        .resolve_handle_for_did(&status_with_handle.author_did)
-        .await;
-    Ok(Json(status_with_handle))
+    let response = StatusWithHandle::from(status_from_db);
+    if let Some(key) = &idempotency_key {
+        // the status is out there now, a retry without the memory still can't duplicate it
+        if let Err(e) = key.remember(&idempotency, &form.0, &response).await {
+            console_error!("saving idempotency key for {} failed: {}", response.uri, e);
+        }
+    }
+
+    Ok(Json(response))
+}
+
+/// Shares a freshly written status as a Bluesky post, returning the post's uri
+async fn crosspost_status(
+    agent: &Agent,
+    config: &AppConfig,
+    status: &Status,
+    saved: &StatusFromDb,
+) -> Result<String, AppError> {
+    let author = match (&saved.handle, saved.handle_verified) {
+        (Some(handle), Some(verified)) if verified != 0 => format!("@{handle}"),
+        _ => status.author_did.to_string(),
+    };
+    let profile_url = format!(
+        "{}/profile/{}",
+        config.public_url,
+        status.author_did.as_str()
+    );
+
+    agent
+        .create_bsky_post(crosspost::status_post(status, &author, &profile_url))
+        .await
+}
+
+/// What a delete leaves behind, so the page can show the author's previous status again
+#[derive(Serialize)]
+pub struct DeleteStatusResponse {
+    deleted: String,
+    current_uri: Option<String>,
+    current_status: Option<String>,
+}
+
+/// Delete one of the logged in user's statuses, both from their repo and from the index
+#[worker::send]
+pub async fn delete_status(
+    State(AppState {
+        oauth,
+        status_db,
+        durable_object,
+        ..
+    }): State<AppState>,
+    session: Session,
+    Path(rkey): Path<String>,
+) -> Result<Json<DeleteStatusResponse>, AppError> {
+    let did: Did = session.get("did").await?.ok_or(AppError::NoSessionAuth)?;
+    let rkey = RecordKey::new(rkey).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    // the uri is built from the session's DID, so this can only ever name the user's own
+    // records. checking the indexed author as well keeps that true if uri parsing changes
+    let uri = format!(
+        "at://{}/{}/{}",
+        did.as_str(),
+        xyz::statusphere::Status::NSID,
+        rkey.as_str()
+    );
+    let existing = match status_db.load_status(&uri).await? {
+        Some(existing) if existing.author_did == did => existing,
+        _ => {
+            return Err(AppError::NotFound(format!(
+                "no status {} for this account",
+                rkey.as_str()
+            )))
+        }
+    };
+
+    let agent = match oauth.restore_session(&did).await {
+        Ok(agent) => agent,
+        Err(err @ AppError::InsufficientScope(_)) => return Err(err),
+        Err(err) => {
+            session.flush().await?;
+            return Err(err);
+        }
+    };
+
+    agent.delete_status(rkey).await?;
+
+    if let Some(post_uri) = &existing.crosspost_uri {
+        // the status is gone either way, a post left behind isn't worth failing over
+        let post_rkey = post_uri
+            .strip_prefix(&format!("at://{}/app.bsky.feed.post/", did.as_str()))
+            .and_then(|rkey| RecordKey::new(rkey.to_string()).ok());
+        match post_rkey {
+            Some(post_rkey) => {
+                if let Err(e) = agent.delete_bsky_post(post_rkey).await {
+                    console_error!("deleting cross-post {} failed: {}", post_uri, e);
+                }
+            }
+            None => console_error!("not deleting unexpected cross-post uri {}", post_uri),
+        }
+    }
+
+    // don't wait for jetstream to echo the delete back
+    status_db
+        .delete_by_uri(&uri)
+        .await
+        .context("deleting status")?;
+    durable_object.broadcast_deletion(&uri).await?;
+
+    let current = agent.current_status().await?;
+
+    Ok(Json(DeleteStatusResponse {
+        deleted: uri,
+        current_uri: current.as_ref().map(|c| c.uri.clone()),
+        current_status: current.map(|c| c.record.status),
+    }))
+}
+
+/// Query parameters for status search. `author` may be a DID or a handle.
+#[derive(Deserialize)]
+pub struct SearchParams {
+    emoji: Option<String>,
+    author: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    indexed_after: Option<DateTime<Utc>>,
+    indexed_before: Option<DateTime<Utc>>,
+    sort_by: Option<TimeField>,
+    order: Option<SortOrder>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    statuses: Vec<StatusWithHandle>,
+    cursor: Option<String>,
+}
+
+/// Search indexed statuses by emoji, author and time range
+#[worker::send]
+pub async fn search(
+    State(AppState {
+        status_db,
+        handle_resolver,
+        ..
+    }): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResponse>, AppError> {
+    let mut query = StatusQuery::new()
+        .sort_by(params.sort_by.unwrap_or_default())
+        .order(params.order.unwrap_or_default());
+
+    if let Some(emoji) = params.emoji {
+        query = query.emoji(emoji);
+    }
+    if let Some(author) = params.author {
+        query = query.author(resolve_actor(&handle_resolver, &author).await?);
+    }
+    if let Some(t) = params.created_after {
+        query = query.created_after(t);
+    }
+    if let Some(t) = params.created_before {
+        query = query.created_before(t);
+    }
+    if let Some(t) = params.indexed_after {
+        query = query.indexed_after(t);
+    }
+    if let Some(t) = params.indexed_before {
+        query = query.indexed_before(t);
+    }
+    if let Some(n) = params.limit {
+        query = query.limit(n);
+    }
+    if let Some(cursor) = params.cursor {
+        query = query.cursor(cursor.parse().map_err(AppError::BadRequest)?);
+    }
+
+    let page = status_db
+        .search(&query)
+        .await
+        .context("searching statuses")?;
+
+    let statuses = page
+        .statuses
+        .into_iter()
+        .map(StatusWithHandle::from)
+        .collect();
+
+    Ok(Json(SearchResponse {
+        statuses,
+        cursor: page.cursor.map(|c| c.to_string()),
+    }))
+}
+
+/// Resolves a user supplied DID or handle (with or without a leading `@`) to a DID
+async fn resolve_actor(handle_resolver: &HandleResolver, actor: &str) -> Result<Did, AppError> {
+    let actor = actor.trim().trim_start_matches('@');
+
+    if actor.starts_with("did:") {
+        return Did::new(actor.to_string()).map_err(|e| AppError::BadRequest(e.to_string()));
+    }
+
+    let handle = Handle::new(actor.to_string()).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    handle_resolver
+        .resolve(&handle)
+        .await
+        .map_err(|e| AppError::BadRequest(format!("could not resolve handle {actor}: {e}")))
+}
+
+// how many statuses a profile page shows
+const PROFILE_PAGE_SIZE: usize = 50;
+
+/// An author's recent statuses. `actor` may be a DID or a handle.
+#[worker::send]
+pub async fn profile(
+    State(AppState {
+        status_db,
+        handle_resolver,
+        ..
+    }): State<AppState>,
+    Path(actor): Path<String>,
+) -> Result<ProfileTemplate, AppError> {
+    let did = resolve_actor(&handle_resolver, &actor).await?;
+
+    let page = status_db
+        .search(
+            &StatusQuery::new()
+                .author(did.clone())
+                .limit(PROFILE_PAGE_SIZE),
+        )
+        .await
+        .context("loading profile statuses")?;
+    let statuses: Vec<StatusWithHandle> = page
+        .statuses
+        .into_iter()
+        .map(StatusWithHandle::from)
+        .collect();
+
+    let handle = statuses
+        .first()
+        .filter(|s| s.handle_verified)
+        .and_then(|s| s.handle.clone());
+
+    Ok(ProfileTemplate {
+        did: did.to_string(),
+        handle,
+        statuses,
+    })
+}
+
+// how many statuses a feed carries
+const FEED_SIZE: usize = 50;
+// feed readers poll on their own schedule, a few minutes stale is fine
+const FEED_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Everyone's recent statuses as RSS or Atom
+#[worker::send]
+pub async fn feed(
+    State(AppState {
+        config, status_db, ..
+    }): State<AppState>,
+    Path(format): Path<FeedFormat>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> Result<Response, AppError> {
+    let statuses = feed_statuses(&status_db, StatusQuery::new()).await?;
+
+    let feed = Feed {
+        title: "Statusphere".to_string(),
+        page_path: "/".to_string(),
+        public_url: &config.public_url,
+        statuses: &statuses,
+    };
+    Ok(feed_response(&feed, format, if_modified_since))
+}
+
+/// One author's recent statuses as RSS or Atom. `actor` may be a DID or a handle.
+#[worker::send]
+pub async fn profile_feed(
+    State(AppState {
+        config,
+        status_db,
+        handle_resolver,
+        ..
+    }): State<AppState>,
+    Path((actor, format)): Path<(String, FeedFormat)>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> Result<Response, AppError> {
+    let did = resolve_actor(&handle_resolver, &actor).await?;
+    let statuses = feed_statuses(&status_db, StatusQuery::new().author(did.clone())).await?;
+
+    let name = statuses
+        .first()
+        .filter(|s| s.handle_verified)
+        .and_then(|s| s.handle.clone())
+        .unwrap_or_else(|| did.to_string());
+    let feed = Feed {
+        title: format!("{name}'s statuses on Statusphere"),
+        page_path: format!("/profile/{}", did.as_str()),
+        public_url: &config.public_url,
+        statuses: &statuses,
+    };
+    Ok(feed_response(&feed, format, if_modified_since))
+}
+
+/// Newest indexed first, so anything new moves the feed's Last-Modified
+async fn feed_statuses(
+    status_db: &StatusDb,
+    query: StatusQuery,
+) -> Result<Vec<StatusWithHandle>, AppError> {
+    let page = status_db
+        .search(&query.sort_by(TimeField::Indexed).limit(FEED_SIZE))
+        .await
+        .context("loading feed statuses")?;
+
+    Ok(page
+        .statuses
+        .into_iter()
+        .map(StatusWithHandle::from)
+        .collect())
+}
+
+fn feed_response(
+    feed: &Feed<'_>,
+    format: FeedFormat,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+) -> Response {
+    // HTTP dates only have whole seconds
+    let last_modified = feed
+        .updated()
+        .map(|t| UNIX_EPOCH + Duration::from_secs(t.timestamp().max(0) as u64));
+
+    let mut response = match (last_modified, if_modified_since) {
+        (Some(last_modified), Some(TypedHeader(since))) if !since.is_modified(last_modified) => {
+            http::StatusCode::NOT_MODIFIED.into_response()
+        }
+        _ => (
+            [(
+                http::header::CONTENT_TYPE,
+                format.content_type().to_string(),
+            )],
+            feed.render(format),
+        )
+            .into_response(),
+    };
+
+    let headers = response.headers_mut();
+    headers.typed_insert(CacheControl::new().with_public().with_max_age(FEED_MAX_AGE));
+    if let Some(last_modified) = last_modified {
+        headers.typed_insert(LastModified::from(last_modified));
+    }
+    response
+}
+
+/// Query parameters for bulk export. `author` may be a DID or a handle.
+#[derive(Deserialize)]
+pub struct ExportParams {
+    format: Option<ExportFormat>,
+    author: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    indexed_after: Option<DateTime<Utc>>,
+    indexed_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    include_handles: bool,
+}
+
+/// Stream the whole status index (optionally filtered) as NDJSON or CSV
+#[worker::send]
+pub async fn export(
+    State(AppState {
+        status_db,
+        handle_resolver,
+        export_token,
+        ..
+    }): State<AppState>,
+    bearer: Result<TypedHeader<Authorization<Bearer>>, TypedHeaderRejection>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, AppError> {
+    // a missing or non-bearer Authorization header is answered the same as a wrong token
+    match (export_token, bearer) {
+        (Some(expected), Ok(TypedHeader(auth))) if secret_matches(&expected, auth.token()) => {}
+        _ => return Err(AppError::NoExportAuth),
+    }
+
+    let mut query = StatusQuery::new().order(SortOrder::Oldest);
+    if let Some(author) = params.author {
+        query = query.author(resolve_actor(&handle_resolver, &author).await?);
+    }
+    if let Some(t) = params.created_after {
+        query = query.created_after(t);
+    }
+    if let Some(t) = params.created_before {
+        query = query.created_before(t);
+    }
+    if let Some(t) = params.indexed_after {
+        query = query.indexed_after(t);
+    }
+    if let Some(t) = params.indexed_before {
+        query = query.indexed_before(t);
+    }
+
+    let format = params.format.unwrap_or_default();
+
+    Ok((
+        [
+            (
+                http::header::CONTENT_TYPE,
+                format.content_type().to_string(),
+            ),
+            (
+                http::header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"statuses.{}\"",
+                    format.file_extension()
+                ),
+            ),
+        ],
+        export_body(status_db, params.include_handles, query, format),
+    )
+        .into_response())
 }
 
 #[worker::send]
@@ -207,32 +914,62 @@ pub async fn websocket(
     durable_object.subscriber_websocket().await
 }
 
+#[derive(Serialize)]
+pub struct HealthResponse {
+    ok: bool,
+    schema: SchemaStatus,
+}
+
+/// Reports whether the database schema is current enough for this build
+#[worker::send]
+pub async fn health(
+    State(AppState { status_db, .. }): State<AppState>,
+) -> Result<(http::StatusCode, Json<HealthResponse>), AppError> {
+    let schema = status_db
+        .verify_schema()
+        .await
+        .context("checking schema version")?;
+
+    let code = if schema.is_current() {
+        http::StatusCode::OK
+    } else {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((
+        code,
+        Json(HealthResponse {
+            ok: schema.is_current(),
+            schema,
+        }),
+    ))
+}
+
+/// Ingests a jetstream event as if the firehose had delivered it. Behind
+/// [`super::auth::require_admin`] like the rest of `/admin/*`.
 #[worker::send]
 pub async fn admin_publish_jetstream_event(
     State(AppState {
         durable_object,
         status_db,
+        actor_handle_resolver,
         ..
     }): State<AppState>,
-    // deliberately only implementing basic authorization because it's not the
-    // focus of this post - do not use this in production apps
-    TypedHeader(auth): TypedHeader<Authorization<headers::authorization::Basic>>,
     Json(status): Json<jetstream::Event<xyz::statusphere::status::RecordData>>,
 ) -> Result<(), AppError> {
-    // TODO: re-deploy with this disabled in some manner
-    // DO NOT USE THIS IN PRODUCTION
-    if auth.username() != "admin" && auth.password() != "hunter2" {
-        return Err(AppError::NoAdminAuth);
-    }
-
-    handle_jetstream_event(
-        &ScheduledEventState {
-            status_db,
-            durable_object,
-        },
-        &status,
-    )
-    .await?;
+    let state = ScheduledEventState {
+        status_db,
+        durable_object,
+        actor_handle_resolver,
+    };
+    if let Some(author) = handle_jetstream_event(&state, &status).await? {
+        if let Err(e) =
+            handles::ensure_actor_handle(&state.status_db, &state.actor_handle_resolver, &author)
+                .await
+        {
+            console_error!("storing handle for {} failed: {}", author.as_str(), e);
+        }
+    }
 
     Ok(())
 }
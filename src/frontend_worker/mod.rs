@@ -1,3 +1,6 @@
+pub mod auth;
 pub mod endpoints;
+pub mod rate_limit;
 pub mod router;
 pub mod state;
+pub mod xrpc;
@@ -0,0 +1,35 @@
+use axum::http::HeaderMap;
+use worker::console_error;
+
+use crate::durable_object::rate_limiter::{RateLimit, RateLimiter};
+use crate::types::errors::AppError;
+
+/// The client's address as seen by Cloudflare. Missing when running outside of it, in which
+/// case only the other limits apply.
+pub fn client_ip(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("CF-Connecting-IP")
+        .and_then(|ip| ip.to_str().ok())
+}
+
+/// Takes a token for `key`, failing with [`AppError::RateLimited`] once the bucket is empty.
+/// A limiter that can't be reached lets the request through, an outage there shouldn't take
+/// status updates down with it.
+pub async fn enforce(
+    limiter: &RateLimiter,
+    key: &str,
+    limit: Option<RateLimit>,
+) -> Result<(), AppError> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+
+    match limiter.take(key, limit).await {
+        Ok(decision) if decision.allowed => Ok(()),
+        Ok(decision) => Err(AppError::RateLimited(decision.retry_after)),
+        Err(e) => {
+            console_error!("rate limiter unavailable for {}, allowing: {}", key, e);
+            Ok(())
+        }
+    }
+}
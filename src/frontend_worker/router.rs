@@ -1,28 +1,67 @@
 use crate::storage::kv::session_state::KvTowerSessionStore;
-use axum::routing::{get, post};
+use crate::types::lexicons::xyz::statusphere::{get_actor_statuses, get_status, get_statuses};
+use axum::middleware;
+use axum::routing::{delete, get, post};
 use axum::Router;
 use tower_sessions::cookie::SameSite;
 use tower_sessions::SessionManagerLayer;
 
+use super::auth;
 use super::endpoints;
 use super::state::AppState;
+use super::xrpc;
 
 pub fn router(state: AppState, session_store: KvTowerSessionStore) -> Router {
     let session_layer = SessionManagerLayer::new(session_store)
         // NOTE: this may not need to be lax, but I think it does (b/c of oauth redirect)
         .with_same_site(SameSite::Lax);
 
+    let admin = Router::new()
+        .route(
+            "/publish_jetstream_event",
+            post(endpoints::admin_publish_jetstream_event),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_admin,
+        ));
+
     axum::Router::new()
         .route("/client-metadata.json", get(endpoints::client_metadata))
+        .route("/jwks.json", get(endpoints::jwks))
         .route("/oauth/callback", get(endpoints::oauth_callback))
+        .route("/oauth/reconsent", get(endpoints::reconsent))
         .route("/login", post(endpoints::login).get(endpoints::home))
         .route("/logout", get(endpoints::logout))
+        .route("/sessions", get(endpoints::list_sessions))
+        .route("/sessions/revoke_all", post(endpoints::revoke_all_sessions))
+        .route("/sessions/{handle}/revoke", post(endpoints::revoke_session))
         .route("/status", post(endpoints::status))
-        .route("/websocket", get(endpoints::websocket))
+        .route("/status/{rkey}", delete(endpoints::delete_status))
+        .route("/search", get(endpoints::search))
+        .route("/profile/{actor}", get(endpoints::profile))
         .route(
-            "/admin/publish_jetstream_event",
-            post(endpoints::admin_publish_jetstream_event),
+            "/profile/{actor}/feed/{format}",
+            get(endpoints::profile_feed),
         )
+        .route("/feed/{format}", get(endpoints::feed))
+        .route("/export", get(endpoints::export))
+        .route(
+            &format!("/xrpc/{}", get_statuses::NSID),
+            get(xrpc::get_statuses),
+        )
+        .route(
+            &format!("/xrpc/{}", get_status::NSID),
+            get(xrpc::get_status),
+        )
+        .route(
+            &format!("/xrpc/{}", get_actor_statuses::NSID),
+            get(xrpc::get_actor_statuses),
+        )
+        .route("/xrpc/{nsid}", get(xrpc::not_implemented))
+        .route("/websocket", get(endpoints::websocket))
+        .route("/health", get(endpoints::health))
+        .nest("/admin", admin)
         .route("/", get(endpoints::home))
         .layer(session_layer)
         .with_state(state)
@@ -1,20 +1,36 @@
 use std::sync::Arc;
 
+use crate::config::AppConfig;
 use crate::durable_object::client::MessageBroker;
+use crate::durable_object::rate_limiter::RateLimiter;
+use crate::services::idempotency::IdempotencyStore;
 use crate::services::oauth::OAuthClient;
-use crate::services::resolvers::DidResolver;
+use crate::services::resolvers::{ActorHandleResolver, HandleResolver};
 use crate::storage::db::StatusDb;
+use crate::storage::kv::session_index::SessionIndex;
 
 #[derive(Clone)]
 pub struct AppState {
+    pub config: &'static AppConfig,
     pub oauth: OAuthClient,
     pub status_db: StatusDb,
     pub durable_object: MessageBroker,
-    pub did_resolver: Arc<DidResolver>,
+    pub rate_limiter: RateLimiter,
+    /// did -> handle, checked in both directions
+    pub actor_handle_resolver: Arc<ActorHandleResolver>,
+    pub handle_resolver: Arc<HandleResolver>,
+    pub sessions: SessionIndex,
+    /// outcomes of status writes made with an `Idempotency-Key`
+    pub idempotency: IdempotencyStore,
+    /// bearer token for the bulk export endpoint, export is disabled if unset
+    pub export_token: Option<Arc<str>>,
+    /// bearer token for `/admin/*`, only admin DIDs with a session get in if unset
+    pub admin_token: Option<Arc<str>>,
 }
 
 #[derive(Clone)]
 pub struct ScheduledEventState {
     pub status_db: StatusDb,
     pub durable_object: MessageBroker,
+    pub actor_handle_resolver: Arc<ActorHandleResolver>,
 }
@@ -0,0 +1,218 @@
+//! Read-only XRPC queries over the status index, so other clients can use statusphere as an
+//! AppView for `xyz.statusphere.*` records.
+
+use atrium_api::types::string::AtIdentifier;
+use atrium_api::types::LimitedNonZeroU8;
+use atrium_common::resolver::Resolver as _;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use worker::console_error;
+
+use crate::storage::query::{StatusPage, StatusQuery};
+use crate::types::errors::AppError;
+use crate::types::lexicons::xyz::statusphere::defs::{StatusView, StatusViewData};
+use crate::types::lexicons::xyz::statusphere::{get_actor_statuses, get_status, get_statuses};
+
+use super::state::AppState;
+
+/// An error in the standard XRPC envelope, `{"error": "...", "message": "..."}`
+#[derive(Debug)]
+pub struct XrpcError {
+    status: StatusCode,
+    error: String,
+    message: Option<String>,
+}
+
+impl XrpcError {
+    fn new(status: StatusCode, error: &str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.to_string(),
+            message: Some(message.into()),
+        }
+    }
+
+    fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidRequest", message)
+    }
+
+    /// One of the errors a lexicon declares. The generated error enums already serialize to
+    /// the envelope, these are all client errors.
+    fn declared<E: Serialize>(e: E) -> Self {
+        let (error, message) = match serde_json::to_value(e) {
+            Ok(serde_json::Value::Object(mut body)) => (
+                body.remove("error")
+                    .and_then(|e| e.as_str().map(str::to_string)),
+                body.remove("message")
+                    .and_then(|m| m.as_str().map(str::to_string)),
+            ),
+            _ => (None, None),
+        };
+
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            error: error.unwrap_or_else(|| "InvalidRequest".to_string()),
+            message,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl IntoResponse for XrpcError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(ErrorBody {
+                error: self.error,
+                message: self.message,
+            }),
+        )
+            .into_response()
+    }
+}
+
+impl From<AppError> for XrpcError {
+    fn from(value: AppError) -> Self {
+        match value {
+            AppError::BadRequest(msg) => XrpcError::invalid_request(msg),
+            AppError::SchemaOutdated { .. } => XrpcError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "InternalServerError",
+                "the index is being upgraded, try again later",
+            ),
+            e => {
+                // details stay in the logs, they can include things like SQL
+                console_error!("xrpc query failed: {e}");
+                XrpcError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "InternalServerError",
+                    "internal server error",
+                )
+            }
+        }
+    }
+}
+
+impl From<QueryRejection> for XrpcError {
+    fn from(value: QueryRejection) -> Self {
+        XrpcError::invalid_request(value.body_text())
+    }
+}
+
+impl From<worker::Error> for XrpcError {
+    fn from(value: worker::Error) -> Self {
+        AppError::from(value).into()
+    }
+}
+
+fn page_query(
+    limit: Option<LimitedNonZeroU8<100u8>>,
+    cursor: Option<String>,
+) -> Result<StatusQuery, XrpcError> {
+    let mut query = StatusQuery::new();
+    if let Some(limit) = limit {
+        query = query.limit(u8::from(limit) as usize);
+    }
+    if let Some(cursor) = cursor {
+        query = query.cursor(cursor.parse().map_err(XrpcError::invalid_request)?);
+    }
+    Ok(query)
+}
+
+fn status_views(page: StatusPage) -> (Vec<StatusView>, Option<String>) {
+    let statuses = page
+        .statuses
+        .into_iter()
+        .map(|s| StatusViewData::from(s).into())
+        .collect();
+    (statuses, page.cursor.map(|c| c.to_string()))
+}
+
+/// `xyz.statusphere.getStatuses`: everyone's statuses, newest first
+#[worker::send]
+pub async fn get_statuses(
+    State(AppState { status_db, .. }): State<AppState>,
+    params: Result<Query<get_statuses::ParametersData>, QueryRejection>,
+) -> Result<Json<get_statuses::Output>, XrpcError> {
+    let Query(params) = params?;
+    let query = page_query(params.limit, params.cursor)?;
+
+    let page = status_db.search(&query).await?;
+    let (statuses, cursor) = status_views(page);
+
+    Ok(Json(get_statuses::OutputData { cursor, statuses }.into()))
+}
+
+/// `xyz.statusphere.getActorStatuses`: one account's statuses, newest first
+#[worker::send]
+pub async fn get_actor_statuses(
+    State(AppState {
+        status_db,
+        handle_resolver,
+        ..
+    }): State<AppState>,
+    params: Result<Query<get_actor_statuses::ParametersData>, QueryRejection>,
+) -> Result<Json<get_actor_statuses::Output>, XrpcError> {
+    let Query(params) = params?;
+
+    let did = match params.actor {
+        AtIdentifier::Did(did) => did,
+        AtIdentifier::Handle(handle) => handle_resolver.resolve(&handle).await.map_err(|e| {
+            XrpcError::declared(get_actor_statuses::Error::ActorNotFound(Some(format!(
+                "could not resolve handle {}: {e}",
+                handle.as_str()
+            ))))
+        })?,
+    };
+
+    let query = page_query(params.limit, params.cursor)?.author(did);
+    let page = status_db.search(&query).await?;
+    let (statuses, cursor) = status_views(page);
+
+    Ok(Json(
+        get_actor_statuses::OutputData { cursor, statuses }.into(),
+    ))
+}
+
+/// `xyz.statusphere.getStatus`: a single status by record URI. Unlike the lists this also
+/// returns expired statuses, the view carries `expiresAt` for the client to decide.
+#[worker::send]
+pub async fn get_status(
+    State(AppState { status_db, .. }): State<AppState>,
+    params: Result<Query<get_status::ParametersData>, QueryRejection>,
+) -> Result<Json<get_status::Output>, XrpcError> {
+    let Query(params) = params?;
+
+    let status = status_db.load_status(&params.uri).await?.ok_or_else(|| {
+        XrpcError::declared(get_status::Error::StatusNotFound(Some(format!(
+            "no status indexed at {}",
+            params.uri
+        ))))
+    })?;
+
+    Ok(Json(
+        get_status::OutputData {
+            status: StatusViewData::from(status).into(),
+        }
+        .into(),
+    ))
+}
+
+/// Any other method under `/xrpc/`
+pub async fn not_implemented(Path(nsid): Path<String>) -> XrpcError {
+    XrpcError::new(
+        StatusCode::NOT_IMPLEMENTED,
+        "MethodNotImplemented",
+        format!("{nsid} is not served here"),
+    )
+}
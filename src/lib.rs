@@ -1,21 +1,28 @@
 use atrium_oauth::DefaultHttpClient;
 // use crate::services::jetstream_listener;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Redirect};
+use config::AppConfig;
 use durable_object::client::MessageBroker;
+use durable_object::rate_limiter::RateLimiter;
 use frontend_worker::{router::router, state::AppState};
-use services::oauth::OAuthClient;
+use services::oauth::{self, OAuthClient};
 use std::sync::Arc;
 use std::time::Duration;
+use storage::kv::cached_resolver::WaitUntil;
+use storage::kv::encryption::Keyring;
+use storage::kv::session_index::SessionIndex;
 use storage::{db::StatusDb, kv::KvStoreWrapper};
 use worker::{
-    console_debug, console_error, console_log, event, Context, Env, HttpRequest, ScheduleContext,
-    ScheduledEvent,
+    console_debug, console_error, console_log, console_warn, event, Context, Env, HttpRequest,
+    ScheduleContext, ScheduledEvent,
 };
 
 use tower::Service as _;
+use types::errors::AppError;
 
-use crate::services::{jetstream::ingest_, resolvers};
+use crate::services::{idempotency, jetstream::ingest_, resolvers};
 
+mod config;
 mod durable_object;
 mod frontend_worker;
 mod services;
@@ -24,58 +31,150 @@ mod types;
 
 const SESSION_STORE_TTL: Duration = Duration::new(60 * 60 * 24 * 30, 0);
 
+/// A worker that can't read its own configuration can't serve anything, say what's wrong with
+/// a 500 so it shows up as a failure rather than a page
+fn config_error(what: &str, e: impl std::fmt::Display) -> axum::response::Response {
+    console_error!("{what} err: {e}");
+    (
+        http::StatusCode::INTERNAL_SERVER_ERROR,
+        format!("{what} err: {e}"),
+    )
+        .into_response()
+}
+
 #[event(fetch, respond_with_errors)]
 async fn fetch(
     req: HttpRequest,
     env: Env,
-    _ctx: Context,
+    ctx: Context,
 ) -> worker::Result<http::Response<axum::body::Body>> {
     console_error_panic_hook::set_once();
 
+    let config = match AppConfig::get(&env) {
+        Ok(config) => config,
+        Err(e) => return Ok(config_error("app config", e)),
+    };
+
+    // one canonical origin keeps OAuth client IDs and session cookies consistent, whatever
+    // hostname or proxy the request came through
+    let host = req
+        .uri()
+        .authority()
+        .map(|a| a.as_str())
+        .unwrap_or_default();
+    if !config.serves_host(host) {
+        console_debug!("redirecting request for {} to {}", host, config.public_url);
+        return Ok(Redirect::permanent(&config.canonical_url(req.uri())).into_response());
+    }
+
     let kv = Arc::new(env.kv("KV")?);
     let status_db = StatusDb::from_env(&env)?;
 
-    let url = {
-        let scheme = match req.uri().scheme() {
-            Some(v) => v,
-            None => return Ok("request URI missing scheme".into_response()),
+    // fail loudly on an outdated schema instead of on the first query that touches it. the
+    // health endpoint is exempt since reporting on exactly this is its job
+    if req.uri().path() != "/health" {
+        let schema = match status_db.verify_schema().await {
+            Ok(schema) => schema,
+            Err(e) => return Ok(AppError::from(e).into_response()),
         };
-        let host = match req.uri().host() {
-            Some(v) => v,
-            None => return Ok("request URI missing host".into_response()),
-        };
-        format!("{}://{}", scheme, host)
+        if !schema.is_current() {
+            return Ok(AppError::SchemaOutdated {
+                applied: schema.applied,
+                required: schema.required,
+            }
+            .into_response());
+        }
+    }
+
+    let keyring = match Keyring::from_env(&env) {
+        Ok(Some(keyring)) => Some(Arc::new(keyring)),
+        Ok(None) => {
+            console_warn!("OAUTH_STORE_KEYS is not set, oauth sessions are stored unencrypted");
+            None
+        }
+        Err(e) => return Ok(config_error("oauth store key config", e)),
+    };
+
+    let resolver_config = match resolvers::ResolverConfig::from_env(&env) {
+        Ok(c) => c,
+        Err(e) => return Ok(config_error("resolver config", e)),
+    };
+
+    let signing_keys = match oauth::signing_keys_from_env(&env) {
+        Ok(keys) => keys,
+        Err(e) => return Ok(config_error("oauth signing key config", e)),
+    };
+
+    let scopes = match oauth::scopes_from_env(&env) {
+        Ok(scopes) => scopes,
+        Err(e) => return Ok(config_error("oauth scope config", e)),
     };
 
-    console_debug!("running with URL: {}", url);
+    // resolver refreshes that outlive a lookup are tied to this request
+    let background = WaitUntil::from(ctx);
 
-    let client = match OAuthClient::new(url.to_string(), &kv) {
+    let client = match OAuthClient::new(
+        config,
+        &kv,
+        keyring,
+        signing_keys,
+        scopes,
+        &resolver_config,
+        &background,
+    ) {
         Ok(c) => c,
         // TODO: move to domain error probably, fixme and etc
-        Err(e) => return Ok(format!("oauth client init err: {}", e).into_response()),
+        Err(e) => return Ok(config_error("oauth client init", e)),
     };
 
     let ns = env.durable_object("MSGBROKER")?;
     let durable_object = MessageBroker::from_namespace(&ns)?;
+    let rate_limiter = RateLimiter::from_namespace(env.durable_object("RATE_LIMITER")?);
 
-    let did_resolver = resolvers::did_resolver(&Arc::new(DefaultHttpClient::default()), &kv);
-    let session_store = KvStoreWrapper::new(kv, "tower:session", SESSION_STORE_TTL);
+    let http_client = Arc::new(DefaultHttpClient::default());
+    let did_resolver = Arc::new(resolvers::did_resolver(
+        &http_client,
+        &kv,
+        &resolver_config,
+        &background,
+    ));
+    let handle_resolver = Arc::new(resolvers::handle_resolver(
+        &http_client,
+        &kv,
+        &resolver_config,
+        &background,
+    ));
+    let actor_handle_resolver =
+        resolvers::actor_handle_resolver(did_resolver, handle_resolver.clone(), &kv, &background);
+    let idempotency = idempotency::store(kv.clone());
+    let session_store = KvStoreWrapper::new(kv.clone(), "tower:session", SESSION_STORE_TTL);
+    let sessions = SessionIndex::new(kv, session_store.clone(), SESSION_STORE_TTL);
 
     let state = AppState {
+        config,
         oauth: client,
         status_db,
         durable_object,
-        did_resolver: Arc::new(did_resolver),
+        rate_limiter,
+        actor_handle_resolver: Arc::new(actor_handle_resolver),
+        handle_resolver,
+        sessions,
+        idempotency,
+        export_token: env
+            .secret("EXPORT_TOKEN")
+            .ok()
+            .map(|s| s.to_string().into()),
+        admin_token: env.secret("ADMIN_TOKEN").ok().map(|s| s.to_string().into()),
     };
 
     Ok(router(state, session_store).call(req).await?)
 }
 
 #[event(scheduled, respond_with_errors)]
-async fn scheduled(_s: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+async fn scheduled(_s: ScheduledEvent, env: Env, ctx: ScheduleContext) {
     console_error_panic_hook::set_once();
 
-    match ingest_(env).await {
+    match ingest_(env, ctx.into()).await {
         Ok(_) => console_log!("done with scheduled jetstream reader"),
         Err(e) => console_error!("error on scheduled jetstream reader, {}", e),
     }
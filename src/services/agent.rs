@@ -1,37 +1,73 @@
 use super::oauth;
 
+use crate::config::StatusRecordMode;
 use crate::types::errors::AppError;
 use crate::types::lexicons::xyz::statusphere::status;
 use crate::types::lexicons::{record::KnownRecord, xyz::statusphere::Status};
+use crate::types::status::StatusDetails;
 use anyhow::Context as _;
 use atrium_api::app::bsky::actor::defs::ProfileViewDetailedData;
 use atrium_api::app::bsky::actor::get_profile;
-use atrium_api::com::atproto::repo::{create_record, list_records};
-use atrium_api::types::TryFromUnknown as _;
+use atrium_api::app::bsky::feed::post;
+use atrium_api::com::atproto::repo::{
+    create_record, delete_record, get_record, list_records, put_record,
+};
+use atrium_api::types::{TryFromUnknown as _, TryIntoUnknown as _};
 use atrium_api::{
     agent::Agent as AtriumAgent,
     types::{
-        string::{Datetime, Did},
+        string::{Cid, Datetime, Did, RecordKey},
         Collection,
     },
 };
+use atrium_xrpc::error::{XrpcError, XrpcErrorKind};
+use worker::console_warn;
+
+/// rkey of the one status record kept in [`StatusRecordMode::Single`]
+pub const CURRENT_STATUS_RKEY: &str = "self";
+
+fn single_rkey() -> RecordKey {
+    CURRENT_STATUS_RKEY.parse().unwrap()
+}
+
+// how many times a single record write re-reads the record after losing a swap race
+const MAX_SWAP_ATTEMPTS: usize = 3;
+
+/// The newest status record in a user's repo
+pub struct CurrentStatus {
+    pub uri: String,
+    pub record: status::RecordData,
+}
 
 pub struct Agent {
     inner: AtriumAgent<oauth::SessionType>,
     did: Did,
+    status_record_mode: StatusRecordMode,
 }
 
 impl Agent {
-    pub fn from_session(session: oauth::SessionType, did: Did) -> Self {
+    pub fn from_session(
+        session: oauth::SessionType,
+        did: Did,
+        status_record_mode: StatusRecordMode,
+    ) -> Self {
         Self {
             did,
             inner: AtriumAgent::new(session),
+            status_record_mode,
         }
     }
 }
 
 impl Agent {
-    pub async fn current_status(&self) -> Result<Option<status::RecordData>, AppError> {
+    pub async fn current_status(&self) -> Result<Option<CurrentStatus>, AppError> {
+        if self.status_record_mode == StatusRecordMode::Single {
+            if let Some((current, _cid)) = self.status_record(&single_rkey()).await? {
+                return Ok(Some(current));
+            }
+            // nothing at the fixed rkey yet, eg the user only posted before this mode was on
+        }
+
         let record = self
             .inner
             .api
@@ -53,10 +89,11 @@ impl Agent {
 
         // take most recent status record from user's repo
         let current_status = if let Some(record) = record.data.records.into_iter().next() {
-            Some(
-                status::RecordData::try_from_unknown(record.data.value)
+            Some(CurrentStatus {
+                uri: record.data.uri,
+                record: status::RecordData::try_from_unknown(record.data.value)
                     .context("decoding status record")?,
-            )
+            })
         } else {
             None
         };
@@ -64,20 +101,34 @@ impl Agent {
         Ok(current_status)
     }
 
+    /// Writes a new status and returns its at:// uri. Depending on the configured
+    /// [`StatusRecordMode`] that's a new record or the user's single status record.
+    ///
+    /// A new record is created at `rkey` when one is given. If that record already exists it's
+    /// taken to be from an earlier attempt at the same write, and its uri is returned.
     pub async fn create_status(
         &self,
         status: String,
-    ) -> Result<create_record::OutputData, AppError> {
+        details: &StatusDetails,
+        rkey: Option<RecordKey>,
+    ) -> Result<String, AppError> {
         let status: KnownRecord = crate::types::lexicons::xyz::statusphere::status::RecordData {
             created_at: Datetime::now(),
+            expires_at: details.expires_at.map(|t| Datetime::new(t.fixed_offset())),
             status,
+            text: details.text.clone(),
+            url: details.url.clone(),
         }
         .into();
 
         // TODO no data validation yet from esquema
         // Maybe you'd like to add it? https://github.com/fatfingers23/esquema/issues/3
 
-        let record = self
+        if self.status_record_mode == StatusRecordMode::Single {
+            return self.put_single_status(status).await;
+        }
+
+        let res = self
             .inner
             .api
             .com
@@ -87,17 +138,191 @@ impl Agent {
                 create_record::InputData {
                     collection: Status::NSID.parse().unwrap(),
                     repo: self.did.clone().into(),
-                    rkey: None,
+                    rkey: rkey.clone(),
                     record: status.into(),
                     swap_commit: None,
                     validate: None,
                 }
                 .into(),
             )
+            .await;
+
+        match (res, rkey) {
+            (Ok(record), _) => Ok(record.data.uri),
+            // PDSes don't agree on an error for an rkey that's taken, so look for the record
+            (Err(e), Some(rkey)) => match self.status_record(&rkey).await {
+                Ok(Some((existing, _))) => {
+                    console_warn!("status {} already exists, not recreating it", existing.uri);
+                    Ok(existing.uri)
+                }
+                _ => Err(e.into()),
+            },
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+
+    /// Overwrites the record at [`CURRENT_STATUS_RKEY`], swapping against the version we last
+    /// read so a concurrent write from another client isn't silently clobbered mid-update.
+    /// Losing the race just means re-reading and trying again, since the newest status wins
+    /// either way.
+    async fn put_single_status(&self, status: KnownRecord) -> Result<String, AppError> {
+        for attempt in 1..=MAX_SWAP_ATTEMPTS {
+            let swap_record = self
+                .status_record(&single_rkey())
+                .await?
+                .and_then(|(_, cid)| cid);
+
+            let res = self
+                .inner
+                .api
+                .com
+                .atproto
+                .repo
+                .put_record(
+                    put_record::InputData {
+                        collection: Status::NSID.parse().unwrap(),
+                        repo: self.did.clone().into(),
+                        rkey: single_rkey(),
+                        record: status.clone().into(),
+                        swap_commit: None,
+                        swap_record,
+                        validate: None,
+                    }
+                    .into(),
+                )
+                .await;
+
+            match res {
+                Ok(record) => return Ok(record.data.uri),
+                Err(atrium_xrpc::Error::XrpcResponse(XrpcError {
+                    error: Some(XrpcErrorKind::Custom(put_record::Error::InvalidSwap(_))),
+                    ..
+                })) => {
+                    console_warn!(
+                        "status record for {} changed under us (attempt {attempt}/{MAX_SWAP_ATTEMPTS})",
+                        self.did.as_str()
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(AppError::Conflict(
+            "your status is being changed somewhere else, try again".to_string(),
+        ))
+    }
+
+    /// The status record at `rkey` with its cid, None if there isn't one
+    async fn status_record(
+        &self,
+        rkey: &RecordKey,
+    ) -> Result<Option<(CurrentStatus, Option<Cid>)>, AppError> {
+        let res = self
+            .inner
+            .api
+            .com
+            .atproto
+            .repo
+            .get_record(
+                get_record::ParametersData {
+                    cid: None,
+                    collection: Status::NSID.parse().unwrap(),
+                    repo: self.did.clone().into(),
+                    rkey: rkey.clone(),
+                }
+                .into(),
+            )
+            .await;
+
+        let record = match res {
+            Ok(record) => record,
+            Err(atrium_xrpc::Error::XrpcResponse(XrpcError {
+                error: Some(XrpcErrorKind::Custom(get_record::Error::RecordNotFound(_))),
+                ..
+            })) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let current = CurrentStatus {
+            uri: record.data.uri,
+            record: status::RecordData::try_from_unknown(record.data.value)
+                .context("decoding status record")?,
+        };
+
+        Ok(Some((current, record.data.cid)))
+    }
+
+    /// Deletes one of the user's status records. Deleting a record that's already gone is not
+    /// an error, so retries are safe.
+    pub async fn delete_status(&self, rkey: RecordKey) -> Result<(), AppError> {
+        self.inner
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(
+                delete_record::InputData {
+                    collection: Status::NSID.parse().unwrap(),
+                    repo: self.did.clone().into(),
+                    rkey,
+                    swap_commit: None,
+                    swap_record: None,
+                }
+                .into(),
+            )
+            .await
+            .context("delete status via agent")?;
+
+        Ok(())
+    }
+
+    /// Publishes an app.bsky.feed.post, returning its at:// uri
+    pub async fn create_bsky_post(&self, post: post::RecordData) -> Result<String, AppError> {
+        let record: atrium_api::record::KnownRecord = post.into();
+
+        let created = self
+            .inner
+            .api
+            .com
+            .atproto
+            .repo
+            .create_record(
+                create_record::InputData {
+                    collection: atrium_api::app::bsky::feed::Post::NSID.parse().unwrap(),
+                    repo: self.did.clone().into(),
+                    rkey: None,
+                    record: record.try_into_unknown().context("encoding bsky post")?,
+                    swap_commit: None,
+                    validate: None,
+                }
+                .into(),
+            )
+            .await
+            .context("publish bsky post via agent")?;
+
+        Ok(created.data.uri)
+    }
+
+    pub async fn delete_bsky_post(&self, rkey: RecordKey) -> Result<(), AppError> {
+        self.inner
+            .api
+            .com
+            .atproto
+            .repo
+            .delete_record(
+                delete_record::InputData {
+                    collection: atrium_api::app::bsky::feed::Post::NSID.parse().unwrap(),
+                    repo: self.did.clone().into(),
+                    rkey,
+                    swap_commit: None,
+                    swap_record: None,
+                }
+                .into(),
+            )
             .await
-            .context("publish status via agent")?;
+            .context("delete bsky post via agent")?;
 
-        Ok(record.data)
+        Ok(())
     }
 
     // TODO: rewrite to directly act on app.bsky.actor.profile record?
@@ -0,0 +1,107 @@
+use atrium_api::app::bsky::embed::external;
+use atrium_api::app::bsky::feed::post;
+use atrium_api::app::bsky::richtext::facet;
+use atrium_api::types::string::Datetime;
+use atrium_api::types::Union;
+
+use crate::types::status::Status;
+
+// long links are shortened in the post text, the facet still points at the full URL
+const MAX_DISPLAYED_URL_CHARS: usize = 40;
+
+/// The Bluesky post shared when someone opts to cross-post a status. It links back to the
+/// author's Statusphere profile, both inline and as an external embed card.
+///
+/// `author` is how the author is named in the post, their handle when we have a verified one.
+pub fn status_post(status: &Status, author: &str, profile_url: &str) -> post::RecordData {
+    let mut text = RichText::default();
+
+    text.push(&format!("Feeling {}", status.status));
+    if let Some(message) = &status.text {
+        text.push(&format!(" — {message}"));
+    }
+    if let Some(url) = &status.url {
+        text.push("\n\n");
+        text.push_link(&display_url(url), url);
+    }
+    text.push("\n\nSet on ");
+    text.push_link("Statusphere", profile_url);
+
+    let description = match &status.text {
+        Some(message) => format!("{author} is feeling {}: {message}", status.status),
+        None => format!("{author} is feeling {}", status.status),
+    };
+    let embed = external::MainData {
+        external: external::ExternalData {
+            description,
+            thumb: None,
+            title: format!("{author} on Statusphere"),
+            uri: profile_url.to_string(),
+        }
+        .into(),
+    };
+
+    post::RecordData {
+        created_at: Datetime::now(),
+        embed: Some(Union::Refs(
+            post::RecordEmbedRefs::AppBskyEmbedExternalMain(Box::new(embed.into())),
+        )),
+        entities: None,
+        facets: Some(text.facets),
+        labels: None,
+        langs: None,
+        reply: None,
+        tags: None,
+        text: text.text,
+    }
+}
+
+/// Post text with link facets. Facet ranges are UTF-8 byte offsets into the text, which is
+/// what `String::len` counts.
+#[derive(Default)]
+struct RichText {
+    text: String,
+    facets: Vec<facet::Main>,
+}
+
+impl RichText {
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    fn push_link(&mut self, label: &str, uri: &str) {
+        let byte_start = self.text.len();
+        self.text.push_str(label);
+
+        self.facets.push(
+            facet::MainData {
+                features: vec![Union::Refs(facet::MainFeaturesItem::Link(Box::new(
+                    facet::LinkData {
+                        uri: uri.to_string(),
+                    }
+                    .into(),
+                )))],
+                index: facet::ByteSliceData {
+                    byte_end: self.text.len(),
+                    byte_start,
+                }
+                .into(),
+            }
+            .into(),
+        );
+    }
+}
+
+/// A link the way Bluesky shows them: without the scheme, and cut short if long
+fn display_url(url: &str) -> String {
+    let url = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+
+    if url.chars().count() <= MAX_DISPLAYED_URL_CHARS {
+        return url.to_string();
+    }
+
+    let shortened: String = url.chars().take(MAX_DISPLAYED_URL_CHARS - 3).collect();
+    format!("{shortened}...")
+}
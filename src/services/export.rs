@@ -0,0 +1,145 @@
+use axum::body::{Body, Bytes};
+use serde::Deserialize;
+use worker::console_error;
+use worker::send::SendFuture;
+
+use crate::storage::db::StatusDb;
+use crate::storage::query::{StatusQuery, MAX_PAGE_SIZE};
+use crate::types::status::StatusWithHandle;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "uri,author_did,handle,handle_verified,status,text,url,expires_at,created_at,indexed_at,seen_on_jetstream,created_via_this_app\n";
+
+struct ExportState {
+    status_db: StatusDb,
+    include_handles: bool,
+    format: ExportFormat,
+    // None once the last page has been emitted
+    next: Option<StatusQuery>,
+    wrote_header: bool,
+}
+
+/// Streams every status matching `query` as a response body, fetching one page from D1 at a
+/// time so the full index never has to sit in memory. Stored handles are only included if
+/// asked for.
+pub fn export_body(
+    status_db: StatusDb,
+    include_handles: bool,
+    query: StatusQuery,
+    format: ExportFormat,
+) -> Body {
+    let state = ExportState {
+        status_db,
+        include_handles,
+        format,
+        // an export is a full copy of the index, expired statuses included
+        next: Some(query.include_expired().limit(MAX_PAGE_SIZE)),
+        wrote_header: false,
+    };
+
+    let stream = futures::stream::unfold(state, |mut state| {
+        SendFuture::new(async move {
+            let query = state.next.take()?;
+
+            let page = match state.status_db.search(&query).await {
+                Ok(page) => page,
+                Err(e) => {
+                    // headers are long gone by now, so all we can do is cut the stream short
+                    console_error!("export: error loading page: {}", e);
+                    return Some((Err(e.to_string()), state));
+                }
+            };
+
+            let mut chunk = String::new();
+            if state.format == ExportFormat::Csv && !state.wrote_header {
+                chunk.push_str(CSV_HEADER);
+                state.wrote_header = true;
+            }
+
+            for s in page.statuses.into_iter() {
+                let mut status = StatusWithHandle::from(s);
+                if !state.include_handles {
+                    status.set_handle(None);
+                }
+
+                match state.format {
+                    ExportFormat::Ndjson => {
+                        // StatusWithHandle is plain data, serializing it can't fail
+                        chunk.push_str(&serde_json::to_string(&status).unwrap_or_default());
+                        chunk.push('\n');
+                    }
+                    ExportFormat::Csv => push_csv_row(&mut chunk, &status),
+                }
+            }
+
+            state.next = page.cursor.map(|c| query.cursor(c));
+
+            Some((Ok(Bytes::from(chunk)), state))
+        })
+    });
+
+    Body::from_stream(stream)
+}
+
+fn push_csv_row(out: &mut String, status: &StatusWithHandle) {
+    let fields = [
+        status.uri.as_str(),
+        status.author_did.as_str(),
+        status.handle.as_deref().unwrap_or(""),
+        &status.handle_verified.to_string(),
+        status.status.as_str(),
+        status.text.as_deref().unwrap_or(""),
+        status.url.as_deref().unwrap_or(""),
+        &status
+            .expires_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        &status.created_at.to_rfc3339(),
+        &status.indexed_at.to_rfc3339(),
+        &status.seen_on_jetstream.to_string(),
+        &status.created_via_this_app.to_string(),
+    ];
+
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_csv_field(out, field);
+    }
+    out.push('\n');
+}
+
+/// RFC 4180 quoting: only quote when needed, doubling any embedded quotes
+fn push_csv_field(out: &mut String, field: &str) {
+    if field.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
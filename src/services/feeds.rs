@@ -0,0 +1,192 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::types::status::StatusWithHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+}
+
+impl FeedFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
+
+    fn path_segment(self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "rss",
+            FeedFormat::Atom => "atom",
+        }
+    }
+}
+
+/// A feed of statuses, newest indexed first
+pub struct Feed<'a> {
+    pub title: String,
+    /// the page this feed mirrors, relative to the public URL, eg `/profile/did:plc:...`
+    pub page_path: String,
+    pub public_url: &'a str,
+    pub statuses: &'a [StatusWithHandle],
+}
+
+impl Feed<'_> {
+    /// When the feed last changed, which is when we last indexed anything in it. Statuses
+    /// carry the author's own createdAt, which can be backdated and wouldn't move this.
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.statuses.iter().map(|s| s.indexed_at).max()
+    }
+
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Rss => self.rss(),
+            FeedFormat::Atom => self.atom(),
+        }
+    }
+
+    fn page_url(&self) -> String {
+        format!("{}{}", self.public_url, self.page_path)
+    }
+
+    fn self_url(&self, format: FeedFormat) -> String {
+        let base = self.page_path.trim_end_matches('/');
+        format!("{}{base}/feed/{}", self.public_url, format.path_segment())
+    }
+
+    fn rss(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n",
+        );
+        push_element(&mut out, "title", &self.title);
+        push_element(&mut out, "link", &self.page_url());
+        push_element(&mut out, "description", &self.title);
+        out.push_str(&format!(
+            "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            escape(&self.self_url(FeedFormat::Rss))
+        ));
+        if let Some(updated) = self.updated() {
+            push_element(&mut out, "lastBuildDate", &updated.to_rfc2822());
+        }
+
+        for s in self.statuses {
+            out.push_str("<item>\n");
+            push_element(&mut out, "title", &entry_title(s));
+            push_element(&mut out, "link", &self.author_url(s));
+            // at:// URIs are stable but not something a browser can open
+            out.push_str(&format!(
+                "<guid isPermaLink=\"false\">{}</guid>\n",
+                escape(&s.uri)
+            ));
+            // RSS wants an email in <author>, Dublin Core takes a plain name
+            push_element(&mut out, "dc:creator", &author_name(s));
+            push_element(&mut out, "pubDate", &s.created_at.to_rfc2822());
+            if let Some(description) = entry_description(s) {
+                push_element(&mut out, "description", &description);
+            }
+            out.push_str("</item>\n");
+        }
+
+        out.push_str("</channel>\n</rss>\n");
+        out
+    }
+
+    fn atom(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+        );
+        push_element(&mut out, "id", &self.self_url(FeedFormat::Atom));
+        push_element(&mut out, "title", &self.title);
+        // Atom requires <updated>, an empty feed has never changed
+        push_element(
+            &mut out,
+            "updated",
+            &self.updated().unwrap_or(DateTime::UNIX_EPOCH).to_rfc3339(),
+        );
+        out.push_str(&format!(
+            "<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+            escape(&self.page_url())
+        ));
+        out.push_str(&format!(
+            "<link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n",
+            escape(&self.self_url(FeedFormat::Atom))
+        ));
+
+        for s in self.statuses {
+            out.push_str("<entry>\n");
+            push_element(&mut out, "id", &s.uri);
+            push_element(&mut out, "title", &entry_title(s));
+            out.push_str(&format!(
+                "<link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n",
+                escape(&self.author_url(s))
+            ));
+            out.push_str("<author>\n");
+            push_element(&mut out, "name", &author_name(s));
+            push_element(&mut out, "uri", &self.author_url(s));
+            out.push_str("</author>\n");
+            push_element(&mut out, "published", &s.created_at.to_rfc3339());
+            // a single-record status is rewritten in place, indexedAt moves with it
+            push_element(&mut out, "updated", &s.indexed_at.to_rfc3339());
+            if let Some(description) = entry_description(s) {
+                push_element(&mut out, "summary", &description);
+            }
+            out.push_str("</entry>\n");
+        }
+
+        out.push_str("</feed>\n");
+        out
+    }
+
+    fn author_url(&self, status: &StatusWithHandle) -> String {
+        format!("{}/profile/{}", self.public_url, status.author_did.as_str())
+    }
+}
+
+/// Verified handle without the @, otherwise the DID. An unverified handle is only a claim.
+fn author_name(status: &StatusWithHandle) -> String {
+    match &status.handle {
+        Some(handle) if status.handle_verified => handle.trim_start_matches('@').to_string(),
+        _ => status.author_did.to_string(),
+    }
+}
+
+fn entry_title(status: &StatusWithHandle) -> String {
+    format!("{} is feeling {}", author_name(status), status.status)
+}
+
+fn entry_description(status: &StatusWithHandle) -> Option<String> {
+    match (&status.text, &status.url) {
+        (Some(text), Some(url)) => Some(format!("{text} {url}")),
+        (Some(text), None) => Some(text.clone()),
+        (None, Some(url)) => Some(url.clone()),
+        (None, None) => None,
+    }
+}
+
+fn push_element(out: &mut String, name: &str, text: &str) {
+    out.push_str(&format!("<{name}>{}</{name}>\n", escape(text)));
+}
+
+/// Escapes text for XML content and attribute values. Characters XML 1.0 can't carry at all
+/// are dropped, status text comes from anyone's repo and one stray control character would
+/// make the whole feed unparseable.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c < ' ' || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
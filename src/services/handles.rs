@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use atrium_api::types::string::{Did, Handle};
+use chrono::{Duration, Utc};
+use worker::console_log;
+
+use crate::services::resolvers::ActorHandleResolver;
+use crate::storage::db::StatusDb;
+
+/// How long a stored handle is trusted before it's checked against the DID document again
+const HANDLE_REFRESH_INTERVAL: Duration = Duration::hours(24);
+
+// cap on how many stale handles one scheduled run re-checks, small enough to fit inside the
+// batch resolution deadline
+const STALE_REFRESH_BATCH: usize = 12;
+
+/// Makes sure the author of a status we're about to store has a reasonably fresh handle in
+/// D1, so readers never need to resolve it themselves
+pub async fn ensure_actor_handle(
+    status_db: &StatusDb,
+    resolver: &ActorHandleResolver,
+    did: &Did,
+) -> anyhow::Result<()> {
+    let verified_at = status_db.actor_handle_verified_at(did).await?;
+    if verified_at.is_some_and(|t| Utc::now() - t < HANDLE_REFRESH_INTERVAL) {
+        return Ok(());
+    }
+
+    let handle = resolver.resolve_handle_for_did(did).await;
+    status_db.save_actor_handle(did, handle.as_ref()).await?;
+
+    Ok(())
+}
+
+/// [`ensure_actor_handle`] for everyone whose statuses an ingest run stored, resolving the ones
+/// that need it concurrently rather than one event at a time
+pub async fn ensure_actor_handles(
+    status_db: &StatusDb,
+    resolver: &ActorHandleResolver,
+    dids: &HashSet<Did>,
+) -> anyhow::Result<()> {
+    let mut due = Vec::new();
+    for did in dids {
+        let verified_at = status_db.actor_handle_verified_at(did).await?;
+        if verified_at.is_none_or(|t| Utc::now() - t >= HANDLE_REFRESH_INTERVAL) {
+            due.push(did);
+        }
+    }
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    // anyone missed by the batch deadline is picked up by the stale refresh later
+    let resolved = resolver.resolve_handles_for_dids(due.iter().copied()).await;
+    for did in due {
+        if let Some(handle) = resolved.get(did) {
+            status_db.save_actor_handle(did, Some(handle)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles an identity event: if it's for someone we have statuses from, re-verify their
+/// handle from scratch and store the result
+pub async fn refresh_actor_handle(
+    status_db: &StatusDb,
+    resolver: &ActorHandleResolver,
+    did: &Did,
+    claimed: Option<&Handle>,
+) -> anyhow::Result<()> {
+    if status_db.actor_handle_verified_at(did).await?.is_none() {
+        // identity events arrive for the whole network, not just our authors
+        return Ok(());
+    }
+
+    console_log!("identity event for {}, refreshing handle", did.as_str());
+    let handle = resolver.refresh_handle_for_did(did, claimed).await;
+    status_db.save_actor_handle(did, handle.as_ref()).await?;
+
+    Ok(())
+}
+
+/// Re-checks a batch of handles that haven't been verified in a while, including authors
+/// whose statuses predate handles being stored at all
+pub async fn refresh_stale_actor_handles(
+    status_db: &StatusDb,
+    resolver: &ActorHandleResolver,
+) -> anyhow::Result<()> {
+    let stale = status_db
+        .stale_actor_handles(Utc::now() - HANDLE_REFRESH_INTERVAL, STALE_REFRESH_BATCH)
+        .await?;
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    console_log!("refreshing {} stale handles", stale.len());
+    let resolved = resolver.resolve_handles_for_dids(&stale).await;
+    for did in stale.iter() {
+        status_db.save_actor_handle(did, resolved.get(did)).await?;
+    }
+
+    Ok(())
+}
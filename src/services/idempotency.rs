@@ -0,0 +1,239 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use atrium_api::types::string::{Did, RecordKey};
+use atrium_common::store::Store as _;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::storage::kv::KvStoreWrapper;
+use crate::types::errors::AppError;
+use crate::types::status::StatusWithHandle;
+
+/// How long a key's outcome is remembered, and how long after its first attempt a write can
+/// still be retried.
+pub const IDEMPOTENCY_WINDOW: Duration = Duration::new(60 * 60 * 24, 0);
+
+const MAX_KEY_LEN: usize = 255;
+
+// how far ahead of our clock a client's attempt time may be
+const MAX_CLOCK_SKEW: Duration = Duration::new(5 * 60, 0);
+
+const TID_ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// What a status write with an `Idempotency-Key` produced, and for which request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotentStatus {
+    /// hash of the request body, a key reused for a different request is an error
+    fingerprint: String,
+    status: StatusWithHandle,
+}
+
+pub type IdempotencyStore = KvStoreWrapper<String, IdempotentStatus>;
+
+pub fn store(kv: Arc<worker::kv::KvStore>) -> IdempotencyStore {
+    KvStoreWrapper::new(kv, "idempotency", IDEMPOTENCY_WINDOW)
+}
+
+/// A client supplied `Idempotency-Key`, scoped to the DID making the request
+pub struct IdempotencyKey {
+    did: Did,
+    key: String,
+}
+
+impl IdempotencyKey {
+    /// Reads the `Idempotency-Key` header, None if the request didn't send one
+    pub fn from_headers(headers: &HeaderMap, did: &Did) -> Result<Option<Self>, AppError> {
+        let Some(value) = headers.get("Idempotency-Key") else {
+            return Ok(None);
+        };
+
+        let key = value
+            .to_str()
+            .map_err(|_| AppError::BadRequest("Idempotency-Key must be ASCII".to_string()))?
+            .trim();
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(AppError::BadRequest(format!(
+                "Idempotency-Key must be 1 to {MAX_KEY_LEN} characters"
+            )));
+        }
+
+        Ok(Some(Self {
+            did: did.clone(),
+            key: key.to_string(),
+        }))
+    }
+
+    /// The outcome of an earlier request with this key, if it was for the same body
+    pub async fn replay(
+        &self,
+        store: &IdempotencyStore,
+        body: &impl Serialize,
+    ) -> Result<Option<StatusWithHandle>, AppError> {
+        let Some(stored) = store
+            .get(&self.store_key())
+            .await
+            .context("loading idempotency key")?
+        else {
+            return Ok(None);
+        };
+
+        if stored.fingerprint != fingerprint(body)? {
+            return Err(AppError::Conflict(
+                "this Idempotency-Key was already used for a different status".to_string(),
+            ));
+        }
+
+        Ok(Some(stored.status))
+    }
+
+    pub async fn remember(
+        &self,
+        store: &IdempotencyStore,
+        body: &impl Serialize,
+        status: &StatusWithHandle,
+    ) -> Result<(), AppError> {
+        let stored = IdempotentStatus {
+            fingerprint: fingerprint(body)?,
+            status: status.clone(),
+        };
+        store
+            .set(self.store_key(), stored)
+            .await
+            .context("saving idempotency key")?;
+
+        Ok(())
+    }
+
+    /// A TID for the record this key creates, so a retried create targets the same record
+    /// even when the stored outcome is gone. Nothing in it depends on when the retry arrives:
+    /// the timestamp is the client's `attempted_at`, sent unchanged with every retry, and the
+    /// clock id comes from the DID and key. Keeping a real timestamp means these records still
+    /// sort by time in the repo, which is how the newest status is found.
+    pub fn record_key(&self, attempted_at: Option<DateTime<Utc>>) -> Result<RecordKey, AppError> {
+        let attempted_at = attempted_at.ok_or_else(|| {
+            AppError::BadRequest(
+                "an Idempotency-Key needs attempted_at, the time of the first attempt".to_string(),
+            )
+        })?;
+        check_attempted_at(attempted_at, Utc::now())?;
+
+        let digest = self.digest();
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        let clock_id = hash & 0x3ff;
+
+        Ok(
+            RecordKey::new(encode_tid(attempted_at.timestamp_micros() as u64, clock_id))
+                .expect("TIDs are valid record keys"),
+        )
+    }
+
+    // user supplied keys can be anything, hashing keeps KV key names short and well formed
+    fn store_key(&self) -> String {
+        format!("{}:{}", self.did.as_str(), hex(&self.digest()))
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.did.as_str());
+        hasher.update([0]);
+        hasher.update(&self.key);
+        hasher.finalize().into()
+    }
+}
+
+/// An attempt can't be from the future, and one older than the window has outlived its key
+fn check_attempted_at(attempted_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<(), AppError> {
+    let (skew, window) = (
+        chrono::Duration::from_std(MAX_CLOCK_SKEW).expect("fits"),
+        chrono::Duration::from_std(IDEMPOTENCY_WINDOW).expect("fits"),
+    );
+    if attempted_at > now + skew {
+        return Err(AppError::BadRequest(
+            "attempted_at is in the future".to_string(),
+        ));
+    }
+    if attempted_at < now - window {
+        return Err(AppError::BadRequest(
+            "attempted_at is older than this Idempotency-Key can be retried for".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn fingerprint(body: &impl Serialize) -> Result<String, AppError> {
+    let json = serde_json::to_vec(body).context("serializing request body")?;
+    Ok(hex(&Sha256::digest(json)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Base32-sortable encoding of a TID: 53 bits of microseconds then a 10 bit clock id
+fn encode_tid(micros: u64, clock_id: u64) -> String {
+    let mut value = ((micros & ((1 << 53) - 1)) << 10) | (clock_id & 0x3ff);
+    let mut chars = [0u8; 13];
+    for c in chars.iter_mut().rev() {
+        *c = TID_ALPHABET[(value & 31) as usize];
+        value >>= 5;
+    }
+
+    String::from_utf8(chars.to_vec()).expect("alphabet is ascii")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> IdempotencyKey {
+        IdempotencyKey {
+            did: Did::new("did:plc:ewvi7nxzyoun6zhxrhs64oiz".to_string()).unwrap(),
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn record_key_only_depends_on_key_and_attempt() {
+        let attempted_at = Utc::now() - chrono::Duration::minutes(90);
+
+        let first = key("a").record_key(Some(attempted_at)).unwrap();
+        // a retry, from a fresh request hours of buckets later
+        let retry = key("a").record_key(Some(attempted_at)).unwrap();
+        assert_eq!(first, retry);
+
+        let other = key("b").record_key(Some(attempted_at)).unwrap();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn record_keys_sort_by_attempt_time() {
+        let earlier = Utc::now() - chrono::Duration::minutes(10);
+        let later = Utc::now() - chrono::Duration::minutes(5);
+
+        let earlier = key("z").record_key(Some(earlier)).unwrap();
+        let later = key("a").record_key(Some(later)).unwrap();
+        assert!(earlier.as_str() < later.as_str());
+    }
+
+    #[test]
+    fn record_key_needs_a_plausible_attempt_time() {
+        assert!(key("a").record_key(None).is_err());
+        assert!(key("a")
+            .record_key(Some(Utc::now() + chrono::Duration::hours(1)))
+            .is_err());
+        assert!(key("a")
+            .record_key(Some(Utc::now() - chrono::Duration::hours(25)))
+            .is_err());
+    }
+
+    #[test]
+    fn encode_tid_is_a_valid_tid() {
+        let tid = encode_tid(1_700_000_000_000_000, 0x3ff);
+        assert_eq!(tid.len(), 13);
+        assert!(atrium_api::types::string::Tid::new(tid).is_ok());
+    }
+}
@@ -1,7 +1,9 @@
 use crate::durable_object::client::MessageBroker;
 use crate::frontend_worker::state::ScheduledEventState;
+use crate::services::{handles, resolvers};
 use crate::storage::db::StatusDb;
-use crate::types::status::Status;
+use crate::storage::kv::cached_resolver::WaitUntil;
+use crate::types::status::{check_record_limits, is_web_url, Status};
 use atrium_api::types::Collection as _;
 use worker::{console_error, console_log, Env, WebSocket};
 
@@ -10,22 +12,57 @@ use worker::WebsocketEvent;
 use crate::types::jetstream::{Event, Operation};
 use crate::types::lexicons::xyz;
 use anyhow::anyhow;
-use atrium_api::types::string::Did;
+use atrium_api::types::string::{Did, Handle};
+use atrium_oauth::DefaultHttpClient;
 use chrono::Utc;
 use futures::StreamExt as _;
+use std::collections::HashSet;
+use std::sync::Arc;
 
 const ALARM_INTERVAL_MS: i64 = 5 * 60 * 1000; // 5 minutes
 const ALARM_INTERVAL_MICROS: i64 = ALARM_INTERVAL_MS * 1000;
 
-pub async fn ingest_(env: Env) -> anyhow::Result<()> {
+/// One scheduled ingest run. `background` is where resolver refreshes that outlive a lookup
+/// are registered.
+pub async fn ingest_(env: Env, background: WaitUntil) -> anyhow::Result<()> {
     let status_db = StatusDb::from_env(&env)?;
 
+    let schema = status_db.verify_schema().await?;
+    if !schema.is_current() {
+        return Err(anyhow!(
+            "refusing to ingest: database schema is at version {} but this build requires version {}",
+            schema.applied,
+            schema.required
+        ));
+    }
+
     let ns = env.durable_object("MSGBROKER")?;
     let durable_object = MessageBroker::from_namespace(&ns)?;
 
+    let kv = Arc::new(env.kv("KV")?);
+    let resolver_config = resolvers::ResolverConfig::from_env(&env)?;
+    let http_client = Arc::new(DefaultHttpClient::default());
+    let actor_handle_resolver = resolvers::actor_handle_resolver(
+        Arc::new(resolvers::did_resolver(
+            &http_client,
+            &kv,
+            &resolver_config,
+            &background,
+        )),
+        Arc::new(resolvers::handle_resolver(
+            &http_client,
+            &kv,
+            &resolver_config,
+            &background,
+        )),
+        &kv,
+        &background,
+    );
+
     let state = ScheduledEventState {
         status_db: status_db.clone(),
         durable_object,
+        actor_handle_resolver: Arc::new(actor_handle_resolver),
     };
 
     let cursor = match status_db.get_jetstream_cursor().await {
@@ -79,6 +116,13 @@ pub async fn ingest_(env: Env) -> anyhow::Result<()> {
         }
     }
 
+    // not worth failing the run over, the next one will pick them up
+    if let Err(e) =
+        handles::refresh_stale_actor_handles(&status_db, &state.actor_handle_resolver).await
+    {
+        console_error!("error refreshing stale handles: {}", e);
+    }
+
     Ok(())
 }
 
@@ -87,6 +131,8 @@ pub async fn ingest(
     cursor: TimestampMicros,
 ) -> anyhow::Result<Option<TimestampMicros>> {
     let mut last_seen = None;
+    // handles are looked up once the stream is done, not per event
+    let mut authors = HashSet::new();
 
     let start_time = Utc::now();
 
@@ -115,7 +161,9 @@ pub async fn ingest(
             WebsocketEvent::Message(message_event) => {
                 let message: Event<xyz::statusphere::status::RecordData> = message_event.json()?;
 
-                handle_jetstream_event(&state, &message).await?;
+                if let Some(author) = handle_jetstream_event(state, &message).await? {
+                    authors.insert(author);
+                }
 
                 if let Some(time_us) = message.time_us {
                     last_seen = Some(time_us);
@@ -131,13 +179,25 @@ pub async fn ingest(
         }
     }
 
+    // a missing handle only means a status shows its DID for a while
+    if let Err(e) =
+        handles::ensure_actor_handles(&state.status_db, &state.actor_handle_resolver, &authors)
+            .await
+    {
+        console_error!("error storing handles for new statuses: {}", e);
+    }
+
     Ok(last_seen)
 }
 
+/// Applies one jetstream event to the index. Returns the author of a status it stored, whose
+/// handle is left to the caller so a whole batch of events can share one round of lookups.
 pub async fn handle_jetstream_event(
     state: &ScheduledEventState,
     event: &Event<xyz::statusphere::status::RecordData>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<Did>> {
+    let mut stored_author = None;
+
     if let Some(commit) = &event.commit {
         console_log!("commit event: {:?}", &event);
 
@@ -148,6 +208,11 @@ pub async fn handle_jetstream_event(
             Operation::Create | Operation::Update => {
                 if let Some(record) = &commit.record {
                     if let Some(ref _cid) = commit.cid {
+                        if let Err(reason) = check_record_limits(record) {
+                            console_log!("dropping {record_uri}: {reason}");
+                            return Ok(None);
+                        }
+
                         let created = record.created_at.as_ref();
                         let right_now = chrono::Utc::now();
 
@@ -158,6 +223,9 @@ pub async fn handle_jetstream_event(
                             status: record.status.clone(),
                             created_at: created.to_utc(),
                             indexed_at: right_now,
+                            text: record.text.clone(),
+                            expires_at: record.expires_at.as_ref().map(|t| t.as_ref().to_utc()),
+                            url: record.url.clone().filter(|u| is_web_url(u)),
                         };
 
                         let updated = state
@@ -166,17 +234,39 @@ pub async fn handle_jetstream_event(
                             .await?;
 
                         state.durable_object.broadcast(updated).await?;
+                        stored_author = Some(status.author_did);
                     }
                 }
             }
             Operation::Delete => {
-                // TODO: could broadcast this to the frontend as an update
                 state.status_db.delete_by_uri(&record_uri).await?;
+                state.durable_object.broadcast_deletion(&record_uri).await?;
             }
         }
     }
 
-    Ok(())
+    if let Some(identity) = &event.identity {
+        // handle changes show up here, and only here
+        let did =
+            Did::new(event.did.clone()).map_err(|s| anyhow!("invalid did from jetstream: {s}"))?;
+        let claimed = identity
+            .handle
+            .as_ref()
+            .and_then(|h| Handle::new(h.clone()).ok());
+        // a stale handle isn't worth stopping ingestion for
+        if let Err(e) = handles::refresh_actor_handle(
+            &state.status_db,
+            &state.actor_handle_resolver,
+            &did,
+            claimed.as_ref(),
+        )
+        .await
+        {
+            console_error!("error refreshing handle for {}: {}", did.as_str(), e);
+        }
+    }
+
+    Ok(stored_author)
 }
 
 type TimestampMicros = u64;
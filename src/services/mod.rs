@@ -1,4 +1,10 @@
 pub mod agent;
+pub mod crosspost;
+pub mod export;
+pub mod feeds;
+pub mod handles;
+pub mod idempotency;
 pub mod jetstream;
 pub mod oauth;
 pub mod resolvers;
+pub mod scopes;
@@ -1,19 +1,26 @@
+use crate::config::{AppConfig, StatusRecordMode};
+use crate::storage::kv::cached_resolver::WaitUntil;
+use crate::storage::kv::encryption::Keyring;
 use crate::storage::kv::KvStoreWrapper;
-use crate::types::errors::AppError;
+use crate::types::errors::{AppError, LoginError};
 
 use atrium_api::types::string::{Did, Handle};
 use atrium_oauth::{
-    AtprotoClientMetadata, AtprotoLocalhostClientMetadata, AuthorizeOptions, CallbackParams,
-    DefaultHttpClient, GrantType, KnownScope, OAuthClient as AtriumOAuthClient, OAuthClientConfig,
-    OAuthClientMetadata, OAuthResolverConfig, Scope,
+    AtprotoClientMetadata, AtprotoLocalhostClientMetadata, AuthorizeOptionPrompt, AuthorizeOptions,
+    CallbackParams, DefaultHttpClient, GrantType, KnownScope, OAuthClient as AtriumOAuthClient,
+    OAuthClientConfig, OAuthClientMetadata, OAuthResolverConfig, Scope,
 };
 use std::{sync::Arc, time::Duration};
 
 use super::agent::Agent;
 use super::resolvers;
+use super::scopes::GrantedScopes;
 use crate::storage::kv::{KvSessionStore, KvStateStore};
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context as _};
 use atrium_api::agent::Agent as AtriumAgent;
+use atrium_common::store::Store as _;
+use jose_jwk::{EcCurves, Jwk, JwkSet, Key};
+use worker::{console_log, console_warn};
 
 pub type ClientType = AtriumOAuthClient<
     KvStateStore,
@@ -31,20 +38,205 @@ pub type SessionType = atrium_oauth::OAuthSession<
 
 const OAUTH_STORE_TTL: Duration = Duration::new(60 * 60 * 24 * 30, 0);
 
+// only what the app actually does: write its own status records, read the user's profile and
+// share statuses as Bluesky posts when asked to. wrangler.toml doesn't repeat these, setting
+// OAUTH_SCOPES there replaces them
+const DEFAULT_SCOPES: &str =
+    "atproto repo:xyz.statusphere.status repo:app.bsky.feed.post rpc:app.bsky.actor.getProfile?aud=*";
+
+/// Parses the `OAUTH_SCOPES` var, a space separated scope list, falling back to
+/// [`DEFAULT_SCOPES`]. `atproto` is always required.
+pub fn scopes_from_env(env: &worker::Env) -> anyhow::Result<Vec<Scope>> {
+    let spec = match env.var("OAUTH_SCOPES") {
+        Ok(scopes) => scopes.to_string(),
+        Err(_) => DEFAULT_SCOPES.to_string(),
+    };
+
+    let scopes: Vec<Scope> = spec
+        .split_whitespace()
+        .map(|scope| match scope {
+            "atproto" => Scope::Known(KnownScope::Atproto),
+            "transition:generic" => Scope::Known(KnownScope::TransitionGeneric),
+            "transition:chat.bsky" => Scope::Known(KnownScope::TransitionChatBsky),
+            other => Scope::Unknown(other.to_string()),
+        })
+        .collect();
+
+    if !scopes.contains(&Scope::Known(KnownScope::Atproto)) {
+        bail!("OAUTH_SCOPES must include atproto");
+    }
+
+    Ok(scopes)
+}
+
+// atrium can only sign client assertions with P-256 keys
+const SIGNING_ALG: &str = "ES256";
+
+/// Loads the client's signing keys from the `OAUTH_SIGNING_KEYS` secret, if set: a JWK set
+/// (`{"keys": [...]}`) of P-256 private keys, each with its own `kid`. The first key signs
+/// client assertions, the rest are only published at `/jwks.json`. To rotate, append the new
+/// key, give authorization servers time to refetch the JWKS, then move it to the front and
+/// eventually drop the old one.
+pub fn signing_keys_from_env(env: &worker::Env) -> anyhow::Result<Option<Vec<Jwk>>> {
+    let secret = match env.secret("OAUTH_SIGNING_KEYS") {
+        Ok(secret) => secret.to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    let jwks: JwkSet =
+        serde_json::from_str(&secret).context("OAUTH_SIGNING_KEYS must be a JWK set")?;
+    if jwks.keys.is_empty() {
+        bail!("OAUTH_SIGNING_KEYS has no keys");
+    }
+
+    // atrium panics on anything other than P-256, so check before handing keys over
+    for key in jwks.keys.iter() {
+        let kid = key
+            .prm
+            .kid
+            .as_deref()
+            .ok_or_else(|| anyhow!("every signing key needs a kid"))?;
+        match &key.key {
+            Key::Ec(ec) if ec.crv == EcCurves::P256 && ec.d.is_some() => {}
+            _ => bail!("signing key {kid} must be a P-256 private key"),
+        }
+    }
+
+    Ok(Some(jwks.keys))
+}
+
+/// What someone can start a login from. atrium resolves handles and DIDs to their PDS, and
+/// treats an https URL as the PDS or entryway itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginIdentifier {
+    Handle(Handle),
+    Did(Did),
+    /// for when handle resolution is broken, or people just know their server
+    Service(String),
+}
+
+impl LoginIdentifier {
+    pub fn as_str(&self) -> &str {
+        match self {
+            LoginIdentifier::Handle(handle) => handle.as_str(),
+            LoginIdentifier::Did(did) => did.as_str(),
+            LoginIdentifier::Service(url) => url,
+        }
+    }
+}
+
+impl std::str::FromStr for LoginIdentifier {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err("Enter your handle, DID, or server URL.".to_string());
+        }
+
+        if input.starts_with("did:") {
+            if !(input.starts_with("did:plc:") || input.starts_with("did:web:")) {
+                return Err("Only did:plc and did:web accounts are supported.".to_string());
+            }
+            return Did::new(input.to_string())
+                .map(LoginIdentifier::Did)
+                .map_err(|_| format!("{input} isn't a valid DID."));
+        }
+
+        if input.starts_with("http://") {
+            return Err("Server URLs must use https.".to_string());
+        }
+        if input.starts_with("https://") {
+            let uri: http::Uri = input
+                .parse()
+                .map_err(|_| format!("{input} isn't a valid URL."))?;
+            if uri.host().is_none_or(str::is_empty) || uri.query().is_some() {
+                return Err(format!(
+                    "{input} isn't a server URL, try https://your.pds.host"
+                ));
+            }
+            return Ok(LoginIdentifier::Service(
+                input.trim_end_matches('/').to_string(),
+            ));
+        }
+
+        // handles are case insensitive and people paste them with the @
+        let handle = input.trim_start_matches('@').to_ascii_lowercase();
+        Handle::new(handle)
+            .map(LoginIdentifier::Handle)
+            .map_err(|_| format!("{input} isn't a valid handle, DID, or https:// server URL."))
+    }
+}
+
 #[derive(Clone)]
 pub struct OAuthClient {
     client: Arc<ClientType>,
+    state_store: KvStateStore,
+    session_store: KvSessionStore,
+    scopes: Vec<Scope>,
+    status_record_mode: StatusRecordMode,
 }
 
 impl OAuthClient {
     pub async fn restore_session(&self, did: &Did) -> Result<Agent, AppError> {
+        self.check_granted_scopes(did).await?;
         let session = self.client.restore(did).await?;
 
-        Ok(Agent::from_session(session, did.clone()))
+        Ok(Agent::from_session(
+            session,
+            did.clone(),
+            self.status_record_mode,
+        ))
+    }
+
+    /// Fails with [`AppError::InsufficientScope`] if the stored session was granted less than
+    /// the scopes we now ask for, eg because the configured scopes changed since login
+    async fn check_granted_scopes(&self, did: &Did) -> Result<(), AppError> {
+        let Some(session) = self
+            .session_store
+            .get(did)
+            .await
+            .context("loading oauth session")?
+        else {
+            // nothing to check, restoring will fail on its own
+            return Ok(());
+        };
+
+        let granted = GrantedScopes::parse(session.token_set.scope.as_deref().unwrap_or_default());
+        let missing: Vec<String> = self
+            .scopes
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|scope| !granted.allows(scope))
+            .map(str::to_string)
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::InsufficientScope(missing))
+        }
     }
 
     pub async fn callback(&self, params: CallbackParams) -> Result<Did, AppError> {
-        let (bsky_session, _) = self.client.callback(params).await?;
+        // atrium deletes state once it's used and KV expires it, so an unknown state is either
+        // a replay or a stale tab. atrium only says so in an error message, so look first
+        if let Some(state) = &params.state {
+            let known = self
+                .state_store
+                .get(state)
+                .await
+                .context("loading oauth state")?;
+            if known.is_none() {
+                return Err(LoginError::StateExpired.into());
+            }
+        }
+
+        let (bsky_session, _) = self
+            .client
+            .callback(params)
+            .await
+            .map_err(|e| LoginError::from_oauth(e, None))?;
         let agent = AtriumAgent::new(bsky_session);
 
         let did = agent.did().await.ok_or(anyhow!(
@@ -54,48 +246,108 @@ impl OAuthClient {
         Ok(did)
     }
 
+    /// Revokes the DID's tokens with its authorization server and forgets the OAuth session.
+    /// The local copy is dropped even if the server can't be reached.
+    pub async fn sign_out(&self, did: &Did) -> Result<(), AppError> {
+        if let Err(e) = self.client.revoke(did).await {
+            console_log!("revoking oauth session for {did:?} failed, dropping it locally: {e}");
+            self.session_store
+                .del(did)
+                .await
+                .context("deleting oauth session")?;
+        }
+
+        Ok(())
+    }
+
     pub fn client_metadata(&self) -> OAuthClientMetadata {
         self.client.client_metadata.clone()
     }
 
-    pub async fn auth_redirect_url(&self, handle: Handle) -> Result<String, AppError> {
+    /// Public halves of the signing keys, empty for a public client
+    pub fn jwks(&self) -> JwkSet {
+        self.client.jwks()
+    }
+
+    pub async fn auth_redirect_url(
+        &self,
+        identifier: &LoginIdentifier,
+    ) -> Result<String, AppError> {
+        self.authorize(identifier.as_str(), None).await
+    }
+
+    /// Sends an already logged in user back through authorization so they can grant the
+    /// scopes their session is missing
+    pub async fn reconsent_redirect_url(&self, did: &Did) -> Result<String, AppError> {
+        self.authorize(did.as_str(), Some(AuthorizeOptionPrompt::Consent))
+            .await
+    }
+
+    async fn authorize(
+        &self,
+        input: &str,
+        prompt: Option<AuthorizeOptionPrompt>,
+    ) -> Result<String, AppError> {
         let auth_url = self
             .client
             .authorize(
-                handle,
+                input,
                 AuthorizeOptions {
-                    scopes: vec![
-                        Scope::Known(KnownScope::Atproto),
-                        Scope::Known(KnownScope::TransitionGeneric),
-                    ],
+                    scopes: self.scopes.clone(),
+                    prompt,
                     ..Default::default()
                 },
             )
-            .await?;
+            .await
+            .map_err(|e| LoginError::from_oauth(e, Some(input)))?;
 
         Ok(auth_url)
     }
 
-    pub fn new(url: String, kv: &Arc<worker::kv::KvStore>) -> anyhow::Result<Self> {
+    pub fn new(
+        config: &AppConfig,
+        kv: &Arc<worker::kv::KvStore>,
+        keyring: Option<Arc<Keyring>>,
+        signing_keys: Option<Vec<Jwk>>,
+        scopes: Vec<Scope>,
+        resolver_config: &resolvers::ResolverConfig,
+        background: &WaitUntil,
+    ) -> anyhow::Result<Self> {
+        let url = &config.public_url;
+        let status_record_mode = config.status_record_mode;
         let http_client = Arc::new(DefaultHttpClient::default());
 
         let resolver = OAuthResolverConfig {
-            did_resolver: resolvers::did_resolver(&http_client, kv),
-            handle_resolver: resolvers::handle_resolver(&http_client, kv),
+            did_resolver: resolvers::did_resolver(&http_client, kv, resolver_config, background),
+            handle_resolver: resolvers::handle_resolver(
+                &http_client,
+                kv,
+                resolver_config,
+                background,
+            ),
             authorization_server_metadata: Default::default(),
             protected_resource_metadata: Default::default(),
         };
 
-        let state_store = KvStoreWrapper::new(kv.clone(), "oauth:state", OAUTH_STORE_TTL);
-        let session_store = KvStoreWrapper::new(kv.clone(), "oauth:session", OAUTH_STORE_TTL);
+        // these hold access and refresh tokens, so they're encrypted at rest when keys are set
+        let state_store = KvStoreWrapper::new(kv.clone(), "oauth:state", OAUTH_STORE_TTL)
+            .with_keyring(keyring.clone());
+        let session_store =
+            KvStoreWrapper::new(kv.clone(), "oauth:session", OAUTH_STORE_TTL).with_keyring(keyring);
+        // kept so sessions can still be dropped locally when server side revocation fails, and
+        // so callbacks can tell an expired login apart from a malformed one
+        let session_store_handle = session_store.clone();
+        let state_store_handle = state_store.clone();
 
         // NOTE: duplicated code here is because TryIntoOAuthClientMetadata is a private trait
-        if url.contains("http://127.0.0.1") {
+        if config.dev_mode {
+            // localhost clients are always public, there's nowhere to publish keys from
+            if signing_keys.is_some() {
+                console_warn!("ignoring OAUTH_SIGNING_KEYS for the localhost development client");
+            }
+
             let client_metadata = AtprotoLocalhostClientMetadata {
-                scopes: Some(vec![
-                    Scope::Known(KnownScope::Atproto),
-                    Scope::Known(KnownScope::TransitionGeneric),
-                ]),
+                scopes: Some(scopes.clone()),
 
                 redirect_uris: Some(vec![format!("{url}/oauth/callback")]),
             };
@@ -110,25 +362,43 @@ impl OAuthClient {
 
             Ok(OAuthClient {
                 client: Arc::new(AtriumOAuthClient::new(config)?),
+                state_store: state_store_handle,
+                session_store: session_store_handle,
+                scopes,
+                status_record_mode,
             })
         } else {
+            // a confidential client gets longer lived refresh tokens, so use one whenever we
+            // have keys to authenticate with
+            let (token_endpoint_auth_method, jwks_uri, token_endpoint_auth_signing_alg) =
+                match &signing_keys {
+                    Some(_) => (
+                        atrium_oauth::AuthMethod::PrivateKeyJwt,
+                        Some(format!("{url}/jwks.json")),
+                        Some(SIGNING_ALG.to_string()),
+                    ),
+                    None => {
+                        console_warn!(
+                            "OAUTH_SIGNING_KEYS is not set, running as a public oauth client"
+                        );
+                        (atrium_oauth::AuthMethod::None, None, None)
+                    }
+                };
+
             let client_metadata = AtprotoClientMetadata {
                 client_id: format!("{url}/client-metadata.json"),
                 client_uri: Some(url.to_string()),
                 redirect_uris: vec![format!("{url}/oauth/callback")],
-                token_endpoint_auth_method: atrium_oauth::AuthMethod::None,
+                token_endpoint_auth_method,
                 grant_types: vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
-                scopes: vec![
-                    Scope::Known(KnownScope::Atproto),
-                    Scope::Known(KnownScope::TransitionGeneric),
-                ],
-                jwks_uri: None,
-                token_endpoint_auth_signing_alg: None,
+                scopes: scopes.clone(),
+                jwks_uri,
+                token_endpoint_auth_signing_alg,
             };
 
             let config = OAuthClientConfig {
                 client_metadata,
-                keys: None,
+                keys: signing_keys,
                 resolver,
                 state_store,
                 session_store,
@@ -136,6 +406,10 @@ impl OAuthClient {
 
             Ok(OAuthClient {
                 client: Arc::new(AtriumOAuthClient::new(config)?),
+                state_store: state_store_handle,
+                session_store: session_store_handle,
+                scopes,
+                status_record_mode,
             })
         }
     }
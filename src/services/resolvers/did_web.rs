@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use atrium_api::did_doc::DidDocument;
+use atrium_api::types::string::Did;
+use atrium_common::resolver::Resolver;
+use atrium_identity::did::CommonDidResolver;
+use atrium_identity::Error;
+use atrium_xrpc::http::header::ACCEPT;
+use atrium_xrpc::http::{Request, Uri};
+use atrium_xrpc::HttpClient;
+
+const DID_WEB_PREFIX: &str = "did:web:";
+
+// hosts that may be fetched over plain http when that's allowed for local development
+const LOOPBACK_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+
+/// did:web resolution per https://w3c-ccg.github.io/did-method-web/. Unlike the atrium
+/// resolver this handles percent-encoded ports (`did:web:localhost%3A8080`) and path based
+/// DIDs (`did:web:example.com:user:alice`).
+pub struct DidWebResolver<T> {
+    http_client: Arc<T>,
+    allow_http_loopback: bool,
+}
+
+impl<T> DidWebResolver<T> {
+    pub fn new(http_client: Arc<T>, allow_http_loopback: bool) -> Self {
+        Self {
+            http_client,
+            allow_http_loopback,
+        }
+    }
+
+    /// Maps a did:web to the URL its document is served from
+    fn document_url(&self, did: &Did) -> Result<Uri, Error> {
+        let invalid = || Error::Did(did.as_str().to_string());
+
+        let id = did
+            .as_str()
+            .strip_prefix(DID_WEB_PREFIX)
+            .ok_or_else(invalid)?;
+        let mut segments = id.split(':');
+
+        // the port separator is the only character the spec requires encoding in the host
+        let host = segments
+            .next()
+            .filter(|h| !h.is_empty())
+            .ok_or_else(invalid)?
+            .replace("%3A", ":")
+            .replace("%3a", ":");
+        let path: Vec<&str> = segments.collect();
+        if path.iter().any(|s| s.is_empty()) {
+            return Err(invalid());
+        }
+
+        let hostname = match host.rsplit_once(':') {
+            Some((hostname, port)) if port.chars().all(|c| c.is_ascii_digit()) => hostname,
+            _ => host.as_str(),
+        };
+        let scheme = if self.allow_http_loopback && LOOPBACK_HOSTS.contains(&hostname) {
+            "http"
+        } else {
+            "https"
+        };
+
+        let url = if path.is_empty() {
+            format!("{scheme}://{host}/.well-known/did.json")
+        } else {
+            format!("{scheme}://{host}/{}/did.json", path.join("/"))
+        };
+
+        Ok(url.parse()?)
+    }
+}
+
+impl<T> Resolver for DidWebResolver<T>
+where
+    T: HttpClient + Send + Sync + 'static,
+{
+    type Input = Did;
+    type Output = DidDocument;
+    type Error = Error;
+
+    async fn resolve(&self, did: &Self::Input) -> Result<Self::Output, Self::Error> {
+        let res = self
+            .http_client
+            .send_http(
+                Request::builder()
+                    .header(ACCEPT, "application/did+ld+json,application/json")
+                    .uri(self.document_url(did)?)
+                    .body(Vec::new())?,
+            )
+            .await
+            .map_err(Error::HttpClient)?;
+
+        if !res.status().is_success() {
+            return Err(Error::HttpStatus(res.status()));
+        }
+
+        let document: DidDocument = serde_json::from_slice(res.body())?;
+
+        // whoever controls the host controls the document, but it still has to claim to be
+        // the DID we asked for
+        if document.id != did.as_str() {
+            return Err(Error::DidDocument(format!(
+                "document for {} has id {}",
+                did.as_str(),
+                document.id
+            )));
+        }
+
+        Ok(document)
+    }
+}
+
+/// Routes did:web to [`DidWebResolver`] and everything else (ie did:plc) to atrium's resolver
+pub struct AppDidResolver<T> {
+    common: CommonDidResolver<T>,
+    web: DidWebResolver<T>,
+}
+
+impl<T> AppDidResolver<T> {
+    pub fn new(common: CommonDidResolver<T>, web: DidWebResolver<T>) -> Self {
+        Self { common, web }
+    }
+}
+
+impl<T> Resolver for AppDidResolver<T>
+where
+    T: HttpClient + Send + Sync + 'static,
+{
+    type Input = Did;
+    type Output = DidDocument;
+    type Error = Error;
+
+    async fn resolve(&self, did: &Self::Input) -> Result<Self::Output, Self::Error> {
+        if did.as_str().starts_with(DID_WEB_PREFIX) {
+            self.web.resolve(did).await
+        } else {
+            self.common.resolve(did).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    use atrium_identity::did::CommonDidResolverConfig;
+    use atrium_xrpc::http::Response;
+
+    use super::*;
+
+    fn did(s: &str) -> Did {
+        Did::new(s.to_string()).unwrap()
+    }
+
+    fn url(allow_http_loopback: bool, did_str: &str) -> Result<String, Error> {
+        DidWebResolver::new(Arc::new(()), allow_http_loopback)
+            .document_url(&did(did_str))
+            .map(|u| u.to_string())
+    }
+
+    #[test]
+    fn document_url_decodes_a_percent_encoded_port() {
+        assert_eq!(
+            url(false, "did:web:example.com%3A8443").unwrap(),
+            "https://example.com:8443/.well-known/did.json"
+        );
+        assert_eq!(
+            url(false, "did:web:example.com%3a8443").unwrap(),
+            "https://example.com:8443/.well-known/did.json"
+        );
+    }
+
+    #[test]
+    fn document_url_maps_path_segments() {
+        assert_eq!(
+            url(false, "did:web:example.com:user:alice").unwrap(),
+            "https://example.com/user/alice/did.json"
+        );
+        assert_eq!(
+            url(false, "did:web:example.com%3A8443:user:alice").unwrap(),
+            "https://example.com:8443/user/alice/did.json"
+        );
+        assert!(url(false, "did:web:example.com::alice").is_err());
+    }
+
+    #[test]
+    fn document_url_only_uses_http_for_loopback_when_allowed() {
+        assert_eq!(
+            url(true, "did:web:localhost%3A8080").unwrap(),
+            "http://localhost:8080/.well-known/did.json"
+        );
+        assert_eq!(
+            url(true, "did:web:127.0.0.1").unwrap(),
+            "http://127.0.0.1/.well-known/did.json"
+        );
+        assert_eq!(
+            url(false, "did:web:localhost%3A8080").unwrap(),
+            "https://localhost:8080/.well-known/did.json"
+        );
+        // allowing http is about local development, not every host
+        assert_eq!(
+            url(true, "did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            url(true, "did:web:localhost.example.com").unwrap(),
+            "https://localhost.example.com/.well-known/did.json"
+        );
+    }
+
+    /// Serves the body built from the server's origin to every request on a loopback port,
+    /// and reports each request path
+    fn serve(body: impl FnOnce(&str) -> String) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let origin = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let body = body(&origin);
+        let (paths, received) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                let _ = paths.send(path);
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+
+        (origin, received)
+    }
+
+    /// A blocking HTTP/1.1 client, just enough to talk to [`serve`]
+    struct LoopbackClient;
+
+    impl HttpClient for LoopbackClient {
+        async fn send_http(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<Response<Vec<u8>>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+            let uri = request.uri();
+            let mut stream = TcpStream::connect(uri.authority().unwrap().as_str())?;
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                uri.path_and_query().unwrap(),
+                uri.authority().unwrap()
+            )?;
+            let mut raw = Vec::new();
+            stream.read_to_end(&mut raw)?;
+
+            let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8_lossy(&raw[..split]);
+            let status: u16 = head.split(' ').nth(1).unwrap().parse()?;
+            Ok(Response::builder()
+                .status(status)
+                .body(raw[split + 4..].to_vec())?)
+        }
+    }
+
+    fn document(id: &str) -> String {
+        serde_json::json!({ "id": id, "alsoKnownAs": ["at://alice.test"] }).to_string()
+    }
+
+    #[test]
+    fn plc_directory_url_override_is_used() {
+        let plc = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+        let (origin, paths) = serve(|_| document(plc));
+        let client = Arc::new(LoopbackClient);
+        let resolver = AppDidResolver::new(
+            CommonDidResolver::new(CommonDidResolverConfig {
+                plc_directory_url: format!("http://{origin}"),
+                http_client: client.clone(),
+            }),
+            DidWebResolver::new(client, false),
+        );
+
+        let doc = futures::executor::block_on(resolver.resolve(&did(plc))).unwrap();
+        assert_eq!(doc.id, plc);
+        assert_eq!(paths.recv().unwrap(), format!("/{plc}"));
+    }
+
+    #[test]
+    fn did_web_resolves_from_a_loopback_port() {
+        let web_did = |origin: &str| format!("did:web:{}", origin.replace(':', "%3A"));
+        let (origin, paths) = serve(|origin| document(&web_did(origin)));
+        let web = web_did(&origin);
+
+        let resolver = DidWebResolver::new(Arc::new(LoopbackClient), true);
+        let doc = futures::executor::block_on(resolver.resolve(&did(&web))).unwrap();
+        assert_eq!(doc.id, web);
+        assert_eq!(paths.recv().unwrap(), "/.well-known/did.json");
+    }
+}
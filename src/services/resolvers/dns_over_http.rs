@@ -1,51 +1,570 @@
+use std::future::Future;
+
 use atrium_identity::handle::DnsTxtResolver;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
 use reqwest_wasm::header::ACCEPT;
 use serde::{Deserialize, Serialize};
+use worker::console_log;
+
+// https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml
+const TYPE_TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+const RCODE_NOERROR: u8 = 0;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_REFUSED: u8 = 5;
+
+// how many times the whole provider list is walked before giving up
+const MAX_ROUNDS: usize = 2;
+
+/// How a provider expects to be queried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DohFormat {
+    /// the `application/dns-json` API offered by Cloudflare and Google
+    Json,
+    /// RFC 8484 `application/dns-message`, which every DoH server supports
+    Wire,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DohProvider {
+    pub url: String,
+    pub format: DohFormat,
+}
 
-pub struct DnsOverHttps(reqwest_wasm::Client);
+impl DohProvider {
+    /// Parses `json:<url>` or `wire:<url>`, a bare url is treated as JSON
+    pub fn parse(spec: &str) -> Self {
+        let spec = spec.trim();
+        match spec.split_once(':') {
+            Some(("wire", url)) => Self {
+                url: url.to_string(),
+                format: DohFormat::Wire,
+            },
+            Some(("json", url)) => Self {
+                url: url.to_string(),
+                format: DohFormat::Json,
+            },
+            _ => Self {
+                url: spec.to_string(),
+                format: DohFormat::Json,
+            },
+        }
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self {
+                url: "https://one.one.one.one/dns-query".to_string(),
+                format: DohFormat::Json,
+            },
+            Self {
+                url: "https://dns.google/resolve".to_string(),
+                format: DohFormat::Json,
+            },
+        ]
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DohError {
+    #[error("request to {provider} failed: {message}")]
+    Transport { provider: String, message: String },
+    #[error("{provider} answered with rcode {rcode}")]
+    Rcode { provider: String, rcode: u8 },
+    #[error("malformed response from {provider}: {message}")]
+    Malformed { provider: String, message: String },
+    #[error("cannot query for invalid name {0}")]
+    InvalidName(String),
+    #[error("no DoH providers configured")]
+    NoProviders,
+}
+
+impl DohError {
+    /// Whether asking another provider might get a better answer. NXDOMAIN and empty answers
+    /// aren't errors at all, and a malformed query name will be malformed everywhere.
+    fn is_transient(&self) -> bool {
+        match self {
+            DohError::Transport { .. } | DohError::Malformed { .. } => true,
+            DohError::Rcode { rcode, .. } => matches!(*rcode, RCODE_SERVFAIL | RCODE_REFUSED),
+            DohError::InvalidName(_) | DohError::NoProviders => false,
+        }
+    }
+}
+
+pub struct DnsOverHttps {
+    client: reqwest_wasm::Client,
+    providers: Vec<DohProvider>,
+}
 
 impl DnsOverHttps {
-    pub fn new() -> Self {
-        Self(reqwest_wasm::Client::new())
+    pub fn new(providers: Vec<DohProvider>) -> Self {
+        Self {
+            client: reqwest_wasm::Client::new(),
+            providers,
+        }
+    }
+
+    async fn query(&self, provider: &DohProvider, name: &str) -> Result<Vec<String>, DohError> {
+        let transport = |e: reqwest_wasm::Error| DohError::Transport {
+            provider: provider.url.clone(),
+            message: e.to_string(),
+        };
+
+        let request = match provider.format {
+            DohFormat::Json => self
+                .client
+                .get(&provider.url)
+                .query(&[("name", name), ("type", "TXT")])
+                .header(ACCEPT, "application/dns-json"),
+            DohFormat::Wire => self
+                .client
+                .get(&provider.url)
+                .query(&[("dns", URL_SAFE_NO_PAD.encode(wire::query(name)?))])
+                .header(ACCEPT, "application/dns-message"),
+        };
+
+        let resp = request.send().await.map_err(transport)?;
+        if !resp.status().is_success() {
+            return Err(DohError::Transport {
+                provider: provider.url.clone(),
+                message: format!("http status {}", resp.status()),
+            });
+        }
+
+        let body = resp.bytes().await.map_err(transport)?;
+        let parsed = match provider.format {
+            DohFormat::Json => json::parse(&body),
+            DohFormat::Wire => wire::parse(&body),
+        };
+
+        let (rcode, records) = parsed.map_err(|message| DohError::Malformed {
+            provider: provider.url.clone(),
+            message,
+        })?;
+
+        answer(provider, rcode, records)
     }
 }
 
+/// What a parsed response means for the lookup
+fn answer(
+    provider: &DohProvider,
+    rcode: u8,
+    records: Vec<String>,
+) -> Result<Vec<String>, DohError> {
+    match rcode {
+        RCODE_NOERROR => Ok(records),
+        // the name doesn't exist, which is an answer and not a failure
+        RCODE_NXDOMAIN => Ok(Vec::new()),
+        rcode => Err(DohError::Rcode {
+            provider: provider.url.clone(),
+            rcode,
+        }),
+    }
+}
+
+/// Asks each provider in turn until one answers, walking the list up to [`MAX_ROUNDS`] times.
+/// Only transient errors move on to the next provider.
+async fn with_failover<'a, F, Fut>(
+    providers: &'a [DohProvider],
+    mut query: F,
+) -> Result<Vec<String>, DohError>
+where
+    F: FnMut(&'a DohProvider) -> Fut,
+    Fut: Future<Output = Result<Vec<String>, DohError>>,
+{
+    let mut last_error = DohError::NoProviders;
+
+    for _ in 0..MAX_ROUNDS {
+        for provider in providers {
+            match query(provider).await {
+                Ok(records) => return Ok(records),
+                Err(e) if e.is_transient() => last_error = e,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
 impl DnsTxtResolver for DnsOverHttps {
     async fn resolve(
         &self,
         query: &str,
     ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-        let url = format!("https://one.one.one.one/dns-query?name={query}&type=TXT");
+        let records = with_failover(&self.providers, |provider| async move {
+            let result = self.query(provider, query).await;
+            if let Err(e) = &result {
+                if e.is_transient() {
+                    console_log!(
+                        "DoH lookup of {} failed, trying next provider: {}",
+                        query,
+                        e
+                    );
+                }
+            }
+            result
+        })
+        .await?;
+
+        Ok(records)
+    }
+}
 
-        let resp = self
-            .0
-            .get(url)
-            .header(ACCEPT, "application/dns-json")
-            .send()
-            .await?;
+/// The JSON API (https://developers.cloudflare.com/1.1.1.1/encryption/dns-over-https/make-api-requests/dns-json/)
+mod json {
+    use super::*;
 
-        let resp = resp.json::<PartialResp>().await?;
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct Resp {
+        #[serde(rename = "Status")]
+        status: u8,
+        // absent on NXDOMAIN and on empty answers
+        #[serde(rename = "Answer", default)]
+        answer: Vec<RespElem>,
+    }
 
-        Ok(resp
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    struct RespElem {
+        #[serde(rename = "type")]
+        record_type: u16,
+        data: String,
+    }
+
+    pub fn parse(body: &[u8]) -> Result<(u8, Vec<String>), String> {
+        let resp: Resp = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+
+        let records = resp
             .answer
             .into_iter()
-            .map(|x| {
-                // TXT-records are (*sometimes*) quoted, but downstream
-                // does not handle quotes well
-                x.data.trim_matches('"').to_string()
-            })
-            .collect())
+            // answers can include the CNAMEs followed to get to the TXT records
+            .filter(|a| a.record_type == TYPE_TXT)
+            .map(|a| join_character_strings(&a.data))
+            .collect();
+
+        Ok((resp.status, records))
+    }
+
+    /// TXT data comes back in presentation format: one or more quoted character-strings, eg
+    /// `"did=did:plc:abc" "def"`, which together make up one record. Providers that don't
+    /// quote send a single unquoted string.
+    pub fn join_character_strings(data: &str) -> String {
+        let data = data.trim();
+        if !data.starts_with('"') {
+            return data.to_string();
+        }
+
+        let mut out = Vec::new();
+        let mut bytes = data.bytes().peekable();
+        let mut in_quotes = false;
+
+        while let Some(b) = bytes.next() {
+            match b {
+                b'"' => in_quotes = !in_quotes,
+                b'\\' if in_quotes => {
+                    // either \DDD (a decimal byte) or a backslash-escaped literal
+                    let mut digits = Vec::new();
+                    while digits.len() < 3 && bytes.peek().is_some_and(u8::is_ascii_digit) {
+                        digits.push(bytes.next().unwrap_or_default());
+                    }
+                    if digits.len() == 3 {
+                        let value = std::str::from_utf8(&digits)
+                            .ok()
+                            .and_then(|d| d.parse::<u8>().ok());
+                        out.extend(value);
+                    } else if digits.is_empty() {
+                        out.extend(bytes.next());
+                    } else {
+                        out.extend(digits);
+                    }
+                }
+                b if in_quotes => out.push(b),
+                // whitespace between character-strings
+                _ => {}
+            }
+        }
+
+        String::from_utf8_lossy(&out).into_owned()
     }
 }
 
-// not modelling errors or misc metadata, just the happy path. PRs are welcome
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct PartialResp {
-    #[serde(rename = "Answer")]
-    answer: Vec<PartialRespElem>,
+/// RFC 8484 / RFC 1035 wire format, only as much of it as TXT lookups need
+mod wire {
+    use super::*;
+
+    pub fn query(name: &str) -> Result<Vec<u8>, DohError> {
+        // id 0 so responses are cacheable (RFC 8484 section 4.1), recursion desired, one question
+        let mut msg = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+
+        for label in name.trim_end_matches('.').split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(DohError::InvalidName(name.to_string()));
+            }
+            msg.push(label.len() as u8);
+            msg.extend(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend(TYPE_TXT.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+
+        Ok(msg)
+    }
+
+    struct Reader<'a> {
+        buf: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, n: usize) -> Result<&[u8], String> {
+            let end = self.pos + n;
+            let bytes = self
+                .buf
+                .get(self.pos..end)
+                .ok_or_else(|| "response truncated".to_string())?;
+            self.pos = end;
+            Ok(bytes)
+        }
+
+        fn u16(&mut self) -> Result<u16, String> {
+            let b = self.take(2)?;
+            Ok(u16::from_be_bytes([b[0], b[1]]))
+        }
+
+        /// Skips a possibly compressed name, we never need to read one
+        fn skip_name(&mut self) -> Result<(), String> {
+            loop {
+                let len = self.take(1)?[0];
+                match len {
+                    0 => return Ok(()),
+                    // a compression pointer always ends the name
+                    l if l & 0xC0 == 0xC0 => {
+                        self.take(1)?;
+                        return Ok(());
+                    }
+                    l => {
+                        self.take(l as usize)?;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn parse(body: &[u8]) -> Result<(u8, Vec<String>), String> {
+        let mut r = Reader { buf: body, pos: 0 };
+
+        let _id = r.u16()?;
+        let flags = r.u16()?;
+        let rcode = (flags & 0x000F) as u8;
+        let questions = r.u16()?;
+        let answers = r.u16()?;
+        r.take(4)?; // authority and additional counts
+
+        for _ in 0..questions {
+            r.skip_name()?;
+            r.take(4)?;
+        }
+
+        let mut records = Vec::new();
+        for _ in 0..answers {
+            r.skip_name()?;
+            let record_type = r.u16()?;
+            r.take(6)?; // class and ttl
+            let len = r.u16()? as usize;
+            let rdata = r.take(len)?;
+
+            if record_type != TYPE_TXT {
+                continue;
+            }
+
+            // one or more length-prefixed character-strings, which together make one record
+            let mut record = Vec::new();
+            let mut data = Reader { buf: rdata, pos: 0 };
+            while data.pos < rdata.len() {
+                let n = data.take(1)?[0] as usize;
+                record.extend(data.take(n)?);
+            }
+            records.push(String::from_utf8_lossy(&record).into_owned());
+        }
+
+        Ok((rcode, records))
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct PartialRespElem {
-    data: String,
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures::executor::block_on;
+
+    use super::*;
+
+    const NAME: &str = "_atproto.alice.test";
+
+    fn provider(url: &str) -> DohProvider {
+        DohProvider::parse(url)
+    }
+
+    /// A response header followed by the question for [`NAME`], as servers echo it back
+    fn response(rcode: u8, answers: u16) -> Vec<u8> {
+        let mut msg = wire::query(NAME).unwrap();
+        msg[2] = 0x81; // response, recursion desired
+        msg[3] = 0x80 | rcode; // recursion available
+        msg[6..8].copy_from_slice(&answers.to_be_bytes());
+        msg
+    }
+
+    /// A TXT answer named by a pointer back to the question name at offset 12
+    fn txt_answer(msg: &mut Vec<u8>, strings: &[&str]) {
+        let rdata: Vec<u8> = strings
+            .iter()
+            .flat_map(|s| std::iter::once(s.len() as u8).chain(s.bytes()))
+            .collect();
+        msg.extend([0xC0, 0x0C]);
+        msg.extend(TYPE_TXT.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+        msg.extend(300u32.to_be_bytes());
+        msg.extend((rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+    }
+
+    #[test]
+    fn nxdomain_without_an_answer_section_is_an_empty_answer() {
+        let (rcode, records) = json::parse(br#"{"Status": 3, "TC": false}"#).unwrap();
+        assert_eq!((rcode, records.len()), (RCODE_NXDOMAIN, 0));
+
+        let (rcode, records) = wire::parse(&response(RCODE_NXDOMAIN, 0)).unwrap();
+        assert_eq!((rcode, records.len()), (RCODE_NXDOMAIN, 0));
+
+        let answered = answer(&provider("https://dns.test"), rcode, records).unwrap();
+        assert!(answered.is_empty());
+    }
+
+    #[test]
+    fn servfail_fails_over_to_the_next_provider() {
+        let providers = [provider("https://a.test"), provider("wire:https://b.test")];
+        let asked = RefCell::new(Vec::new());
+
+        let records = block_on(with_failover(&providers, |p| {
+            asked.borrow_mut().push(p.url.clone());
+            async move {
+                match p.format {
+                    DohFormat::Json => answer(p, RCODE_SERVFAIL, Vec::new()),
+                    DohFormat::Wire => answer(p, RCODE_NOERROR, vec!["did=did:plc:abc".into()]),
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(records, ["did=did:plc:abc"]);
+        assert_eq!(*asked.borrow(), ["https://a.test", "https://b.test"]);
+    }
+
+    #[test]
+    fn failover_gives_up_after_every_round_and_stops_on_final_answers() {
+        let providers = [provider("https://a.test"), provider("https://b.test")];
+
+        let asked = RefCell::new(0);
+        let err = block_on(with_failover(&providers, |p| {
+            *asked.borrow_mut() += 1;
+            async move { answer(p, RCODE_REFUSED, Vec::new()) }
+        }))
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            DohError::Rcode {
+                rcode: RCODE_REFUSED,
+                ..
+            }
+        ));
+        assert_eq!(*asked.borrow(), providers.len() * MAX_ROUNDS);
+
+        // a name that can't be queried won't do any better elsewhere
+        let asked = RefCell::new(0);
+        let err = block_on(with_failover(&providers, |_| {
+            *asked.borrow_mut() += 1;
+            async { Err(DohError::InvalidName("..".to_string())) }
+        }))
+        .unwrap_err();
+        assert!(matches!(err, DohError::InvalidName(_)));
+        assert_eq!(*asked.borrow(), 1);
+
+        assert!(matches!(
+            block_on(with_failover(
+                &[],
+                |p| async move { answer(p, 0, Vec::new()) }
+            )),
+            Err(DohError::NoProviders)
+        ));
+    }
+
+    #[test]
+    fn txt_split_into_character_strings_is_one_record() {
+        let body = br#"{"Status": 0, "Answer": [
+            {"name": "_atproto.alice.test", "type": 5, "data": "alice.example."},
+            {"name": "_atproto.alice.test", "type": 16, "data": "\"did=did:plc:\" \"abc\""}
+        ]}"#;
+        let (_, records) = json::parse(body).unwrap();
+        assert_eq!(records, ["did=did:plc:abc"]);
+
+        assert_eq!(
+            json::join_character_strings("did=did:plc:abc"),
+            "did=did:plc:abc"
+        );
+        assert_eq!(
+            json::join_character_strings(r#""a \"quoted\" \065\\b""#),
+            r#"a "quoted" A\b"#
+        );
+
+        let mut msg = response(RCODE_NOERROR, 1);
+        txt_answer(&mut msg, &["did=did:plc:", "abc"]);
+        let (_, records) = wire::parse(&msg).unwrap();
+        assert_eq!(records, ["did=did:plc:abc"]);
+    }
+
+    #[test]
+    fn wire_names_can_mix_labels_and_compression_pointers() {
+        let mut msg = response(RCODE_NOERROR, 2);
+
+        // a CNAME named by a label followed by a pointer into the question, then the TXT
+        msg.extend([4]);
+        msg.extend(b"next");
+        msg.extend([0xC0, 0x0C]);
+        msg.extend(5u16.to_be_bytes());
+        msg.extend(CLASS_IN.to_be_bytes());
+        msg.extend(300u32.to_be_bytes());
+        msg.extend(2u16.to_be_bytes());
+        msg.extend([0xC0, 0x0C]);
+        txt_answer(&mut msg, &["did=did:plc:abc"]);
+
+        let (rcode, records) = wire::parse(&msg).unwrap();
+        assert_eq!(rcode, RCODE_NOERROR);
+        assert_eq!(records, ["did=did:plc:abc"]);
+    }
+
+    #[test]
+    fn truncated_or_malformed_wire_responses_are_errors() {
+        let mut msg = response(RCODE_NOERROR, 1);
+        txt_answer(&mut msg, &["did=did:plc:abc"]);
+
+        for len in 0..msg.len() {
+            assert!(wire::parse(&msg[..len]).is_err(), "parsed {len} bytes");
+        }
+
+        // more answers claimed than sent
+        let mut short = msg.clone();
+        short[7] = 2;
+        assert!(wire::parse(&short).is_err());
+
+        // a character-string running past the end of its rdata
+        let mut overrun = msg.clone();
+        let last_string = msg.len() - "did=did:plc:abc".len() - 1;
+        overrun[last_string] = 200;
+        assert!(wire::parse(&overrun).is_err());
+
+        assert!(json::parse(b"<html>not json</html>").is_err());
+    }
 }
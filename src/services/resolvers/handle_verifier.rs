@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use atrium_api::types::string::{Did, Handle};
+use atrium_common::resolver::Resolver;
+use atrium_identity::Error;
+use serde::{Deserialize, Serialize};
+
+use super::{DidResolver, HandleResolver};
+
+/// The handle a DID document claims, and whether that handle points back at the DID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActorHandle {
+    pub handle: Handle,
+    pub verified: bool,
+}
+
+/// Resolves a DID to its handle, checking the claim in both directions. Anyone can put any
+/// handle in their DID document, it only counts if the handle also resolves to that DID.
+pub struct HandleVerifier {
+    dids: Arc<DidResolver>,
+    handles: Arc<HandleResolver>,
+}
+
+impl HandleVerifier {
+    pub fn new(dids: Arc<DidResolver>, handles: Arc<HandleResolver>) -> Self {
+        Self { dids, handles }
+    }
+
+    /// Refreshes the cached DID document and, if given, the handle it now claims, so the next
+    /// verification sees current data rather than whatever was cached before the change
+    pub async fn refresh_sources(&self, did: &Did, claimed: Option<&Handle>) -> Result<(), Error> {
+        self.dids.refresh(did).await?;
+        if let Some(handle) = claimed {
+            // a handle that didn't resolve a minute ago may well resolve now
+            if let Err(e) = self.handles.refresh(handle).await {
+                if !matches!(e, Error::NotFound) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Resolver for HandleVerifier {
+    type Input = Did;
+    type Output = ActorHandle;
+    type Error = Error;
+
+    async fn resolve(&self, did: &Self::Input) -> Result<Self::Output, Self::Error> {
+        let doc = self.dids.resolve(did).await?;
+
+        // also known as list is in priority order so take the first handle
+        let handle = doc
+            .also_known_as
+            .unwrap_or_default()
+            .iter()
+            .find_map(|aka| aka.strip_prefix("at://"))
+            .and_then(|h| Handle::new(h.to_string()).ok())
+            .ok_or(Error::NotFound)?;
+
+        let verified = match self.handles.resolve(&handle).await {
+            Ok(resolved) => &resolved == did,
+            // the handle doesn't resolve at all, so the claim is definitely bogus. anything else
+            // might be transient and is left to the negative cache to retry
+            Err(Error::NotFound) => false,
+            Err(e) => return Err(e),
+        };
+
+        Ok(ActorHandle { handle, verified })
+    }
+}
@@ -1,57 +1,238 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 
-use atrium_api::types::string::Did;
+use anyhow::{bail, Context as _};
+use atrium_api::types::string::{Did, Handle};
 use atrium_common::resolver::Resolver as _;
 use atrium_identity::{
     did::{CommonDidResolver, CommonDidResolverConfig, DEFAULT_PLC_DIRECTORY_URL},
     handle::{AtprotoHandleResolver, AtprotoHandleResolverConfig},
 };
 use atrium_oauth::DefaultHttpClient;
-use dns_over_http::DnsOverHttps;
-use worker::{console_log, kv::KvStore};
+use did_web::{AppDidResolver, DidWebResolver};
+use dns_over_http::{DnsOverHttps, DohProvider};
+use futures::future::{self, Either};
+use futures::StreamExt as _;
+use worker::{console_log, kv::KvStore, Delay, Env};
 
-use crate::storage::kv::cached_resolver::KvStoreCachedResolver;
+use crate::storage::kv::cached_resolver::{KvStoreCachedResolver, ResolverCacheConfig, WaitUntil};
 
+mod did_web;
 mod dns_over_http;
+mod handle_verifier;
 
-pub fn did_resolver(http_client: &Arc<DefaultHttpClient>, kv: &Arc<KvStore>) -> DidResolver {
+pub use handle_verifier::ActorHandle;
+use handle_verifier::HandleVerifier;
+
+// did documents rarely change and are on the render path for every status, so keep them
+// around for a while and lean on stale-while-revalidate
+const DID_CACHE_CONFIG: ResolverCacheConfig = ResolverCacheConfig {
+    fresh_ttl: Duration::new(60 * 60 * 6, 0),
+    stale_ttl: Duration::new(60 * 60 * 24 * 3, 0),
+    negative_ttl: Duration::new(60 * 5, 0),
+    memory_capacity: 500,
+};
+
+// a page of statuses shouldn't hammer KV or the network, and shouldn't wait forever on it
+const MAX_CONCURRENT_RESOLUTIONS: usize = 6;
+const BATCH_RESOLUTION_DEADLINE: Duration = Duration::from_millis(1500);
+
+// handle -> did lookups back handle verification and login
+const HANDLE_CACHE_CONFIG: ResolverCacheConfig = ResolverCacheConfig {
+    fresh_ttl: Duration::new(60 * 60, 0),
+    stale_ttl: Duration::new(60 * 60 * 6, 0),
+    negative_ttl: Duration::new(60, 0),
+    memory_capacity: 500,
+};
+
+// verified handles are rendered next to every status, and a handle changing or going invalid
+// should show up within the hour
+const ACTOR_HANDLE_CACHE_CONFIG: ResolverCacheConfig = ResolverCacheConfig {
+    fresh_ttl: Duration::new(60 * 60, 0),
+    stale_ttl: Duration::new(60 * 60 * 24, 0),
+    negative_ttl: Duration::new(60 * 5, 0),
+    memory_capacity: 500,
+};
+
+/// Where identities are resolved from. Overridable so the app can run against a local PLC
+/// and PDS in integration tests or on a private network.
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    pub plc_directory_url: String,
+    /// fetch did:web documents for localhost over plain http, for local development only
+    pub did_web_allow_http: bool,
+    /// DNS-over-HTTPS servers used for handle TXT lookups, tried in order
+    pub doh_providers: Vec<DohProvider>,
+}
+
+impl ResolverConfig {
+    pub fn from_env(env: &Env) -> anyhow::Result<Self> {
+        let plc_directory_url = match env.var("PLC_DIRECTORY_URL") {
+            Ok(url) => url.to_string(),
+            Err(_) => DEFAULT_PLC_DIRECTORY_URL.to_string(),
+        };
+        plc_directory_url
+            .parse::<http::Uri>()
+            .with_context(|| format!("invalid PLC_DIRECTORY_URL {plc_directory_url}"))?;
+
+        let did_web_allow_http = env
+            .var("DID_WEB_ALLOW_HTTP")
+            .is_ok_and(|v| v.to_string() == "true");
+
+        let doh_providers = match env.var("DOH_PROVIDERS") {
+            Ok(providers) => providers
+                .to_string()
+                .split(',')
+                .filter(|p| !p.trim().is_empty())
+                .map(DohProvider::parse)
+                .collect(),
+            Err(_) => DohProvider::defaults(),
+        };
+        if doh_providers.is_empty() {
+            bail!("DOH_PROVIDERS is set but lists no providers");
+        }
+
+        Ok(Self {
+            plc_directory_url,
+            did_web_allow_http,
+            doh_providers,
+        })
+    }
+}
+
+pub fn did_resolver(
+    http_client: &Arc<DefaultHttpClient>,
+    kv: &Arc<KvStore>,
+    config: &ResolverConfig,
+    background: &WaitUntil,
+) -> DidResolver {
     KvStoreCachedResolver::new(
-        CommonDidResolver::new(CommonDidResolverConfig {
-            plc_directory_url: DEFAULT_PLC_DIRECTORY_URL.to_string(),
-            http_client: http_client.clone(),
-        }),
+        AppDidResolver::new(
+            CommonDidResolver::new(CommonDidResolverConfig {
+                plc_directory_url: config.plc_directory_url.clone(),
+                http_client: http_client.clone(),
+            }),
+            DidWebResolver::new(http_client.clone(), config.did_web_allow_http),
+        ),
         kv.clone(),
-        "resolved::did",
+        // entries are wrapped in a CacheEntry now, so they live under a new prefix
+        "resolved:v2:did",
+        DID_CACHE_CONFIG,
+        background.clone(),
     )
 }
 
-pub type DidResolver = KvStoreCachedResolver<CommonDidResolver<DefaultHttpClient>>;
+pub type DidResolver = KvStoreCachedResolver<AppDidResolver<DefaultHttpClient>>;
 
-impl DidResolver {
-    pub async fn resolve_handle_for_did(&self, did: &Did) -> Option<String> {
+pub fn actor_handle_resolver(
+    dids: Arc<DidResolver>,
+    handles: Arc<HandleResolver>,
+    kv: &Arc<KvStore>,
+    background: &WaitUntil,
+) -> ActorHandleResolver {
+    KvStoreCachedResolver::new(
+        HandleVerifier::new(dids, handles),
+        kv.clone(),
+        "resolved:v2:did-handle",
+        ACTOR_HANDLE_CACHE_CONFIG,
+        background.clone(),
+    )
+}
+
+pub type ActorHandleResolver = KvStoreCachedResolver<HandleVerifier>;
+
+impl ActorHandleResolver {
+    /// Looks up the handle to display for a DID, None if it has none or resolution failed
+    pub async fn resolve_handle_for_did(&self, did: &Did) -> Option<ActorHandle> {
         match self.resolve(did).await {
-            Ok(did_doc) => {
-                // also known as list is in priority order so take first
-                did_doc
-                    .also_known_as
-                    .and_then(|akas| akas.first().map(|s| format!("@{}", s).replace("at://", "")))
+            Ok(handle) => {
+                if !handle.verified {
+                    console_log!(
+                        "handle {} does not resolve back to {}",
+                        handle.handle.as_str(),
+                        did.as_str()
+                    );
+                }
+                Some(handle)
             }
             Err(err) => {
-                console_log!("Error resolving did: {err}");
+                console_log!("Error resolving handle for did: {err}");
+                None
+            }
+        }
+    }
+
+    /// Resolves handles for many DIDs at once, each distinct DID only once and at most
+    /// [`MAX_CONCURRENT_RESOLUTIONS`] at a time. Anything not resolved within
+    /// [`BATCH_RESOLUTION_DEADLINE`] is left out so one slow PDS can't hold up a whole page.
+    pub async fn resolve_handles_for_dids<'a>(
+        &self,
+        dids: impl IntoIterator<Item = &'a Did>,
+    ) -> HashMap<Did, ActorHandle> {
+        let unique: HashSet<&Did> = dids.into_iter().collect();
+
+        let mut lookups = futures::stream::iter(unique)
+            .map(|did| async move { (did, self.resolve_handle_for_did(did).await) })
+            .buffer_unordered(MAX_CONCURRENT_RESOLUTIONS);
+        let deadline = Delay::from(BATCH_RESOLUTION_DEADLINE);
+        futures::pin_mut!(deadline);
+
+        let mut resolved = HashMap::new();
+        loop {
+            match future::select(lookups.next(), &mut deadline).await {
+                Either::Left((Some((did, handle)), _)) => {
+                    if let Some(handle) = handle {
+                        resolved.insert(did.clone(), handle);
+                    }
+                }
+                Either::Left((None, _)) => break,
+                Either::Right(_) => {
+                    console_log!("handle resolution deadline hit, rendering without the rest");
+                    break;
+                }
+            }
+        }
+
+        resolved
+    }
+
+    /// Re-verifies the handle for a DID, bypassing every cache along the way. `claimed` is the
+    /// handle an identity event announced, if any.
+    pub async fn refresh_handle_for_did(
+        &self,
+        did: &Did,
+        claimed: Option<&Handle>,
+    ) -> Option<ActorHandle> {
+        if let Err(err) = self.inner().refresh_sources(did, claimed).await {
+            console_log!("Error refreshing identity of {}: {err}", did.as_str());
+            return None;
+        }
+        match self.refresh(did).await {
+            Ok(handle) => Some(handle),
+            Err(err) => {
+                console_log!("Error resolving handle for did: {err}");
                 None
             }
         }
     }
 }
 
-pub fn handle_resolver(http_client: &Arc<DefaultHttpClient>, kv: &Arc<KvStore>) -> HandleResolver {
+pub fn handle_resolver(
+    http_client: &Arc<DefaultHttpClient>,
+    kv: &Arc<KvStore>,
+    config: &ResolverConfig,
+    background: &WaitUntil,
+) -> HandleResolver {
     KvStoreCachedResolver::new(
         AtprotoHandleResolver::new(AtprotoHandleResolverConfig {
-            dns_txt_resolver: DnsOverHttps::new(),
+            dns_txt_resolver: DnsOverHttps::new(config.doh_providers.clone()),
             http_client: http_client.clone(),
         }),
         kv.clone(),
-        "resolved:handle",
+        "resolved:v2:handle",
+        HANDLE_CACHE_CONFIG,
+        background.clone(),
     )
 }
 
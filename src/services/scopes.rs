@@ -0,0 +1,202 @@
+//! Comparing OAuth scopes by what they permit rather than by how they're spelled.
+//!
+//! Authorization servers are free to hand back granular scopes normalized or expanded, eg
+//! `repo:xyz.statusphere.status` as `repo?collection=xyz.statusphere.status&action=create&...`,
+//! or several `rpc:` scopes merged into one with multiple `lxm` values. Comparing strings would
+//! send those sessions back through consent on every write.
+
+use std::collections::HashSet;
+
+// what a `repo` scope allows when it doesn't list actions
+const REPO_ACTIONS: [&str; 3] = ["create", "update", "delete"];
+const WILDCARD: &str = "*";
+
+/// One thing a scope permits. Granular scopes expand to one of these per combination of their
+/// parameters, anything else is only ever equal to itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Grant {
+    Repo { collection: String, action: String },
+    Rpc { method: String, audience: String },
+    Other(String),
+}
+
+impl Grant {
+    fn covers(&self, wanted: &Grant) -> bool {
+        match (self, wanted) {
+            (
+                Grant::Repo { collection, action },
+                Grant::Repo {
+                    collection: wanted_collection,
+                    action: wanted_action,
+                },
+            ) => action == wanted_action && matches(collection, wanted_collection),
+            (
+                Grant::Rpc { method, audience },
+                Grant::Rpc {
+                    method: wanted_method,
+                    audience: wanted_audience,
+                },
+            ) => matches(method, wanted_method) && matches(audience, wanted_audience),
+            (granted, wanted) => granted == wanted,
+        }
+    }
+}
+
+fn matches(granted: &str, wanted: &str) -> bool {
+    granted == WILDCARD || granted == wanted
+}
+
+/// Expands a scope string into what it grants. A granular scope too malformed to grant
+/// anything is kept whole, so it still has to be granted exactly.
+fn parse(scope: &str) -> Vec<Grant> {
+    let grants = parse_granular(scope);
+    if grants.is_empty() {
+        vec![Grant::Other(scope.to_string())]
+    } else {
+        grants
+    }
+}
+
+fn parse_granular(scope: &str) -> Vec<Grant> {
+    let (resource, query) = scope.split_once('?').unwrap_or((scope, ""));
+    let (resource, positional) = match resource.split_once(':') {
+        Some((resource, positional)) => (resource, Some(positional.to_string())),
+        None => (resource, None),
+    };
+    let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let values = |key: &str| -> Vec<String> {
+        params
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
+    };
+
+    match resource {
+        "repo" => {
+            let collections: Vec<String> =
+                positional.into_iter().chain(values("collection")).collect();
+            let mut actions = values("action");
+            if actions.is_empty() {
+                actions = REPO_ACTIONS.iter().map(|a| a.to_string()).collect();
+            }
+
+            collections
+                .iter()
+                .flat_map(|collection| {
+                    actions.iter().map(|action| Grant::Repo {
+                        collection: collection.clone(),
+                        action: action.clone(),
+                    })
+                })
+                .collect()
+        }
+        "rpc" => {
+            let methods: Vec<String> = positional.into_iter().chain(values("lxm")).collect();
+            // an rpc scope without an audience is malformed, it matches only its own kind
+            let audiences = match values("aud") {
+                audiences if audiences.is_empty() => vec![String::new()],
+                audiences => audiences,
+            };
+
+            methods
+                .iter()
+                .flat_map(|method| {
+                    audiences.iter().map(|audience| Grant::Rpc {
+                        method: method.clone(),
+                        audience: audience.clone(),
+                    })
+                })
+                .collect()
+        }
+        _ => vec![Grant::Other(scope.to_string())],
+    }
+}
+
+/// The scopes a session was granted, from a token response's space separated `scope`
+pub struct GrantedScopes {
+    grants: HashSet<Grant>,
+    // sessions from before the move to granular scopes, which covers any repo or rpc scope
+    transition_generic: bool,
+}
+
+impl GrantedScopes {
+    pub fn parse(scopes: &str) -> Self {
+        let scopes: Vec<&str> = scopes.split_whitespace().collect();
+        Self {
+            grants: scopes.iter().flat_map(|scope| parse(scope)).collect(),
+            transition_generic: scopes.contains(&"transition:generic"),
+        }
+    }
+
+    /// Whether everything `scope` asks for was granted, by whichever scopes granted it
+    pub fn allows(&self, scope: &str) -> bool {
+        parse(scope).iter().all(|wanted| {
+            (self.transition_generic && matches!(wanted, Grant::Repo { .. } | Grant::Rpc { .. }))
+                || self.grants.iter().any(|granted| granted.covers(wanted))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "rpc:app.bsky.actor.getProfile?aud=*";
+
+    #[test]
+    fn identical_scopes_are_granted() {
+        let granted = GrantedScopes::parse("atproto repo:xyz.statusphere.status");
+        assert!(granted.allows("atproto"));
+        assert!(granted.allows("repo:xyz.statusphere.status"));
+        assert!(!granted.allows("repo:app.bsky.feed.post"));
+        assert!(!granted.allows("transition:chat.bsky"));
+        // naming no collection permits nothing, it's only granted as is
+        assert!(!granted.allows("repo"));
+        assert!(GrantedScopes::parse("repo").allows("repo"));
+    }
+
+    #[test]
+    fn expanded_repo_scopes_match_their_short_form() {
+        let granted = GrantedScopes::parse(
+            "atproto repo?collection=xyz.statusphere.status&collection=app.bsky.feed.post&action=create&action=update&action=delete",
+        );
+        assert!(granted.allows("repo:xyz.statusphere.status"));
+        assert!(granted.allows("repo:app.bsky.feed.post?action=create"));
+
+        // create alone doesn't cover the default of every action
+        let granted = GrantedScopes::parse("repo:xyz.statusphere.status?action=create");
+        assert!(granted.allows("repo:xyz.statusphere.status?action=create"));
+        assert!(!granted.allows("repo:xyz.statusphere.status"));
+
+        let granted = GrantedScopes::parse("repo:*");
+        assert!(granted.allows("repo:xyz.statusphere.status"));
+    }
+
+    #[test]
+    fn rpc_scopes_compare_by_method_and_audience() {
+        let granted = GrantedScopes::parse(
+            "rpc?lxm=app.bsky.actor.getProfile&lxm=app.bsky.feed.getTimeline&aud=%2A",
+        );
+        assert!(granted.allows(PROFILE));
+
+        let granted = GrantedScopes::parse(
+            "rpc:app.bsky.actor.getProfile?aud=did:web:api.bsky.app%23bsky_appview",
+        );
+        assert!(
+            granted.allows("rpc:app.bsky.actor.getProfile?aud=did:web:api.bsky.app#bsky_appview")
+        );
+        // one audience doesn't cover all of them
+        assert!(!granted.allows(PROFILE));
+    }
+
+    #[test]
+    fn transition_generic_covers_granular_scopes() {
+        let granted = GrantedScopes::parse("atproto transition:generic");
+        assert!(granted.allows("repo:xyz.statusphere.status"));
+        assert!(granted.allows(PROFILE));
+        assert!(!granted.allows("transition:chat.bsky"));
+    }
+}
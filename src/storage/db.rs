@@ -1,31 +1,141 @@
+use super::query::{StatusPage, StatusQuery};
+use crate::services::resolvers::ActorHandle;
 use crate::types::status::{Status, StatusFromDb};
+use atrium_api::types::string::Did;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use worker::{console_debug, query, D1Database, Result};
+use worker::{console_debug, console_log, query, D1Database, Result};
+
+/// Schema version this build expects: the number of the newest file in `migrations/`
+pub const REQUIRED_SCHEMA_VERSION: u32 = 10;
+
+// set once the schema has been seen at (or above) the required version in this isolate.
+// outdated results are deliberately not cached so applying migrations takes effect without
+// waiting for isolates to be recycled
+static VERIFIED_SCHEMA_VERSION: AtomicU32 = AtomicU32::new(0);
+
+/// Status columns plus the author's stored handle, for `SELECT`s
+pub(super) const STATUS_WITH_HANDLE: &str = "SELECT status.*, actor_handle.handle, actor_handle.handleVerified FROM status LEFT JOIN actor_handle ON actor_handle.did = status.authorDid";
+
+// RETURNING can't join, so writes pick up the author's handle with subqueries instead
+const RETURNING_STATUS_WITH_HANDLE: &str = "RETURNING *,
+    (SELECT handle FROM actor_handle WHERE did = status.authorDid) AS handle,
+    (SELECT handleVerified FROM actor_handle WHERE did = status.authorDid) AS handleVerified";
+
+// upsert SET clause that keeps whichever write of a uri was created last. timestamps are
+// compared as dates since their text forms don't all have the same precision. a newer write
+// isn't the status that was cross-posted, so it drops the link (an echo of the same write
+// keeps it)
+const KEEP_NEWEST_STATUS: &str = "status = CASE WHEN julianday(excluded.createdAt) >= julianday(status.createdAt) THEN excluded.status ELSE status.status END,
+    text = CASE WHEN julianday(excluded.createdAt) >= julianday(status.createdAt) THEN excluded.text ELSE status.text END,
+    expiresAt = CASE WHEN julianday(excluded.createdAt) >= julianday(status.createdAt) THEN excluded.expiresAt ELSE status.expiresAt END,
+    url = CASE WHEN julianday(excluded.createdAt) >= julianday(status.createdAt) THEN excluded.url ELSE status.url END,
+    createdAt = CASE WHEN julianday(excluded.createdAt) >= julianday(status.createdAt) THEN excluded.createdAt ELSE status.createdAt END,
+    crosspostUri = CASE WHEN julianday(excluded.createdAt) > julianday(status.createdAt) THEN NULL ELSE status.crosspostUri END";
+
+/// Leaves out statuses whose author set them to expire and that time has passed
+pub(super) const NOT_EXPIRED: &str =
+    "(status.expiresAt IS NULL OR julianday(status.expiresAt) > julianday('now'))";
 
 #[derive(Clone)]
 pub struct StatusDb(Arc<D1Database>);
 
+/// Result of comparing the applied schema version against the one this build needs
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SchemaStatus {
+    pub applied: u32,
+    pub required: u32,
+}
+
+impl SchemaStatus {
+    pub fn is_current(&self) -> bool {
+        self.applied >= self.required
+    }
+}
+
 impl StatusDb {
     pub fn from_env(env: &worker::Env) -> worker::Result<Self> {
         let d1 = env.d1("DB")?;
         Ok(Self(Arc::new(d1)))
     }
 
+    /// Reads the applied schema version. The version table itself only arrived in migration
+    /// 0006, so databases without it report version 0.
+    pub async fn schema_version(&self) -> Result<u32> {
+        let has_version_table = query!(
+            &self.0,
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'"
+        )
+        .first::<String>(Some("name"))
+        .await?
+        .is_some();
+
+        if !has_version_table {
+            return Ok(0);
+        }
+
+        let version = query!(
+            &self.0,
+            "SELECT MAX(version) AS version FROM schema_version"
+        )
+        .first::<Option<u32>>(Some("version"))
+        .await?
+        .flatten();
+
+        Ok(version.unwrap_or(0))
+    }
+
+    /// Checks the applied schema version, hitting the database only until it has been seen to
+    /// be current once in this isolate
+    pub async fn verify_schema(&self) -> Result<SchemaStatus> {
+        let cached = VERIFIED_SCHEMA_VERSION.load(Ordering::Relaxed);
+        if cached >= REQUIRED_SCHEMA_VERSION {
+            return Ok(SchemaStatus {
+                applied: cached,
+                required: REQUIRED_SCHEMA_VERSION,
+            });
+        }
+
+        let status = SchemaStatus {
+            applied: self.schema_version().await?,
+            required: REQUIRED_SCHEMA_VERSION,
+        };
+
+        if status.is_current() {
+            VERIFIED_SCHEMA_VERSION.store(status.applied, Ordering::Relaxed);
+        } else {
+            console_log!(
+                "database schema version {} is older than required version {}",
+                status.applied,
+                status.required
+            );
+        }
+
+        Ok(status)
+    }
+
     // optimistic update from local write. Due to race conditions sometimes this hits the db after
-    // an update from jetstream from the same uri
+    // an update from jetstream from the same uri. A uri can also be written more than once (the
+    // single record status mode), so only a newer createdAt replaces the stored status
     pub async fn save_optimistic(&self, status: &Status) -> Result<StatusFromDb> {
-        let res = query!(&self.0, r#"INSERT INTO status (uri, authorDid, status, createdAt, indexedAt, seenOnJetstream, createdViaThisApp) VALUES (?1, ?2, ?3, ?4, ?5, FALSE, TRUE)
+        let res = query!(&self.0, &format!(r#"INSERT INTO status (uri, authorDid, status, createdAt, indexedAt, text, expiresAt, url, seenOnJetstream, createdViaThisApp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, FALSE, TRUE)
                       ON CONFLICT (uri)
                       DO UPDATE
                       SET
-                        createdViaThisApp = TRUE
-                      RETURNING *
-                      "#,
+                        createdViaThisApp = TRUE,
+                        {KEEP_NEWEST_STATUS}
+                      {RETURNING_STATUS_WITH_HANDLE}
+                      "#),
                     &status.uri,
                     &status.author_did,
                     &status.status,
                     &status.created_at,
                     &status.indexed_at,
+                    &status.text,
+                    &status.expires_at,
+                    &status.url,
         )?.first(None).await?;
 
         // insert or update should _always_ return one row
@@ -37,24 +147,23 @@ impl StatusDb {
     /// Saves or updates a status by its did(uri), returning the created/updated row
     pub async fn save_or_update_from_jetstream(&self, status: &Status) -> Result<StatusFromDb> {
         console_debug!("save or update from jetstream: {:?}", &status);
-        let res = query!(&self.0, r#"INSERT INTO status (uri, authorDid, status, createdAt, indexedAt, seenOnJetstream, createdViaThisApp) VALUES (?1, ?2, ?3, ?4, ?5, TRUE, FALSE)
+        let res = query!(&self.0, &format!(r#"INSERT INTO status (uri, authorDid, status, createdAt, indexedAt, text, expiresAt, url, seenOnJetstream, createdViaThisApp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, TRUE, FALSE)
                       ON CONFLICT (uri)
                       DO UPDATE
                       SET
-                        status = ?6,
-                        indexedAt = ?7,
-                        seenOnJetstream = TRUE 
-                      RETURNING *
-                      "#,  
-                    // insert
+                        {KEEP_NEWEST_STATUS},
+                        indexedAt = excluded.indexedAt,
+                        seenOnJetstream = TRUE
+                      {RETURNING_STATUS_WITH_HANDLE}
+                      "#),
                     &status.uri,
                     &status.author_did,
                     &status.status,
                     &status.created_at,
                     &status.indexed_at,
-                    // update
-                    &status.status,
-                    &status.indexed_at,
+                    &status.text,
+                    &status.expires_at,
+                    &status.url,
         )?.first(None).await?;
         // insert or update should _always_ return one row
         let res = res.ok_or(worker::Error::Infallible)?;
@@ -64,6 +173,33 @@ impl StatusDb {
         Ok(res)
     }
 
+    /// Loads a single status by its uri
+    pub async fn load_status(&self, uri: &str) -> Result<Option<StatusFromDb>> {
+        query!(
+            &self.0,
+            &format!("{STATUS_WITH_HANDLE} WHERE status.uri = ?1"),
+            &uri
+        )?
+        .first(None)
+        .await
+    }
+
+    /// Links a status to the Bluesky post it was shared as, returning the updated row
+    pub async fn save_crosspost_uri(&self, uri: &str, post_uri: &str) -> Result<StatusFromDb> {
+        let res = query!(
+            &self.0,
+            &format!(
+                "UPDATE status SET crosspostUri = ?2 WHERE uri = ?1 {RETURNING_STATUS_WITH_HANDLE}"
+            ),
+            &uri,
+            &post_uri
+        )?
+        .first(None)
+        .await?;
+
+        res.ok_or(worker::Error::Infallible)
+    }
+
     /// delete a status
     pub async fn delete_by_uri(&self, uri: &str) -> Result<()> {
         query!(&self.0, "DELETE FROM status WHERE uri = ?1", &uri)?
@@ -73,11 +209,11 @@ impl StatusDb {
         Ok(())
     }
 
-    /// Loads the last n statuses we have saved
+    /// Loads the last n unexpired statuses we have saved, with their authors' stored handles
     pub async fn load_latest_statuses(&self, n: usize) -> Result<Vec<StatusFromDb>> {
         query!(
             &self.0,
-            "SELECT * FROM status ORDER BY indexedAt DESC LIMIT ?1",
+            &format!("{STATUS_WITH_HANDLE} WHERE {NOT_EXPIRED} ORDER BY indexedAt DESC LIMIT ?1"),
             n
         )?
         .all()
@@ -85,6 +221,99 @@ impl StatusDb {
         .results()
     }
 
+    /// Runs a filtered search over the status table, one page at a time
+    pub async fn search(&self, q: &StatusQuery) -> Result<StatusPage> {
+        let (sql, binds) = q.to_sql()?;
+        console_debug!("status search: {}", &sql);
+
+        let statuses = self.0.prepare(sql).bind(&binds)?.all().await?.results()?;
+
+        Ok(q.paginate(statuses))
+    }
+
+    /// When the stored handle for `did` was last checked, None if we've never stored one
+    pub async fn actor_handle_verified_at(&self, did: &Did) -> Result<Option<DateTime<Utc>>> {
+        query!(
+            &self.0,
+            "SELECT verifiedAt FROM actor_handle WHERE did = ?1",
+            did
+        )?
+        .first::<DateTime<Utc>>(Some("verifiedAt"))
+        .await
+    }
+
+    /// Records the result of checking an author's handle. A failed check (`None`) only bumps
+    /// the check time, so a flaky resolver doesn't wipe out a handle we already know.
+    pub async fn save_actor_handle(&self, did: &Did, handle: Option<&ActorHandle>) -> Result<()> {
+        let now = Utc::now();
+        match handle {
+            Some(handle) => {
+                query!(
+                    &self.0,
+                    r#"INSERT INTO actor_handle (did, handle, handleVerified, verifiedAt) VALUES (?1, ?2, ?3, ?4)
+                      ON CONFLICT (did)
+                      DO UPDATE
+                      SET
+                        handle = excluded.handle,
+                        handleVerified = excluded.handleVerified,
+                        verifiedAt = excluded.verifiedAt
+                      "#,
+                    did,
+                    &handle.handle,
+                    handle.verified,
+                    &now,
+                )?
+                .run()
+                .await?;
+            }
+            None => {
+                query!(
+                    &self.0,
+                    r#"INSERT INTO actor_handle (did, handle, handleVerified, verifiedAt) VALUES (?1, NULL, FALSE, ?2)
+                      ON CONFLICT (did)
+                      DO UPDATE
+                      SET
+                        verifiedAt = excluded.verifiedAt
+                      "#,
+                    did,
+                    &now,
+                )?
+                .run()
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Authors whose handle hasn't been checked since `before`, or never has been. Compared as
+    /// dates, like search, since stored check times don't all have the same precision.
+    pub async fn stale_actor_handles(
+        &self,
+        before: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Did>> {
+        #[derive(Deserialize)]
+        struct Row {
+            did: Did,
+        }
+
+        let rows = query!(
+            &self.0,
+            r#"SELECT DISTINCT status.authorDid AS did FROM status
+                LEFT JOIN actor_handle ON actor_handle.did = status.authorDid
+                WHERE actor_handle.verifiedAt IS NULL OR julianday(actor_handle.verifiedAt) < julianday(?1)
+                LIMIT ?2"#,
+            &before,
+            limit
+        )?
+        .all()
+        .await?
+        .results::<Row>()?;
+
+        Ok(rows.into_iter().map(|r| r.did).collect())
+    }
+
     /// Gets the last seen jetstream cursor timestamp
     pub async fn get_jetstream_cursor(&self) -> Result<Option<u64>> {
         let result = query!(&self.0, "SELECT last_seen_timestamp FROM jetstream_cursor")
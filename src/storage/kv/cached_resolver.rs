@@ -1,24 +1,192 @@
-use atrium_common::resolver::{CachedResolver, Resolver};
+use atrium_common::resolver::Resolver;
 use atrium_common::types::cached::r#impl::{Cache as _, CacheImpl};
 use atrium_common::types::cached::CacheConfig;
-use serde::{de::DeserializeOwned, Serialize};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use worker::send::SendWrapper;
+use worker::{console_debug, console_log, Context, ScheduleContext};
 
 use super::{KvStoreError, KvStoreWrapper};
 use atrium_common::store::Store as _;
-use std::fmt::Debug;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::future::Future;
 use std::hash::Hash;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 use std::time::Duration;
 
-const CACHE_TTL: Duration = Duration::new(60 * 60 * 6, 0);
+/// How long resolved values are trusted, how long failures are remembered, and how many
+/// entries are kept in isolate memory
+#[derive(Debug, Clone, Copy)]
+pub struct ResolverCacheConfig {
+    /// entries younger than this are served without touching the network
+    pub fresh_ttl: Duration,
+    /// entries older than `fresh_ttl` but younger than this are served immediately while a
+    /// refresh runs in the background, after this they're gone
+    pub stale_ttl: Duration,
+    /// how long a failed resolution is remembered before the network is tried again
+    pub negative_ttl: Duration,
+    pub memory_capacity: u64,
+}
+
+/// Lets work outlive the response that started it. Futures are handed to the runtime's
+/// `waitUntil`, without which it's free to cancel them as soon as the response is sent.
+#[derive(Clone)]
+pub struct WaitUntil(Arc<SendWrapper<InvocationContext>>);
+
+enum InvocationContext {
+    Fetch(Context),
+    Scheduled(ScheduleContext),
+}
+
+impl WaitUntil {
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'static) {
+        match &self.0 .0 {
+            InvocationContext::Fetch(ctx) => ctx.wait_until(future),
+            InvocationContext::Scheduled(ctx) => ctx.wait_until(future),
+        }
+    }
+}
+
+impl From<Context> for WaitUntil {
+    fn from(value: Context) -> Self {
+        Self(Arc::new(SendWrapper(InvocationContext::Fetch(value))))
+    }
+}
+
+impl From<ScheduleContext> for WaitUntil {
+    fn from(value: ScheduleContext) -> Self {
+        Self(Arc::new(SendWrapper(InvocationContext::Scheduled(value))))
+    }
+}
+
+/// A cached resolution, shared by the memory and KV tiers. Failures are cached as entries
+/// without a value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry<V> {
+    value: Option<V>,
+    error: Option<String>,
+    /// the failure was "doesn't exist", which callers handle differently from "couldn't check"
+    #[serde(default)]
+    not_found: bool,
+    fetched_at_ms: i64,
+}
+
+impl<V> CacheEntry<V> {
+    fn resolved(value: V) -> Self {
+        Self {
+            value: Some(value),
+            error: None,
+            not_found: false,
+            fetched_at_ms: Utc::now().timestamp_millis(),
+        }
+    }
+
+    fn failed(error: &impl CacheableError) -> Self {
+        Self {
+            value: None,
+            error: Some(error.to_string()),
+            not_found: error.is_not_found(),
+            fetched_at_ms: Utc::now().timestamp_millis(),
+        }
+    }
+
+    fn age(&self) -> Duration {
+        let age_ms = Utc::now().timestamp_millis() - self.fetched_at_ms;
+        Duration::from_millis(age_ms.max(0) as u64)
+    }
+}
+
+enum Freshness {
+    Fresh,
+    Stale,
+    Expired,
+}
+
+/// Returned in place of re-running a resolution that recently failed
+#[derive(Debug, thiserror::Error)]
+pub enum CachedFailure {
+    #[error("cached resolution failure: not found")]
+    NotFound,
+    #[error("cached resolution failure: {0}")]
+    Other(String),
+}
+
+/// Resolver errors the cache can remember and hand back later
+pub trait CacheableError: Display + From<CachedFailure> {
+    fn is_not_found(&self) -> bool;
+}
+
+impl CacheableError for atrium_identity::Error {
+    fn is_not_found(&self) -> bool {
+        matches!(self, atrium_identity::Error::NotFound)
+    }
+}
+
+impl From<CachedFailure> for atrium_identity::Error {
+    fn from(value: CachedFailure) -> Self {
+        match value {
+            // so a remembered NotFound still means "doesn't exist" to whoever matches on it
+            CachedFailure::NotFound => atrium_identity::Error::NotFound,
+            other => atrium_identity::Error::HttpClient(Box::new(other)),
+        }
+    }
+}
+
+/// The parts of a cache that live as long as the isolate: resolvers are rebuilt for every
+/// request, these are looked up by KV prefix so each request picks up where the last one left
+/// off
+struct IsolateState<I, O> {
+    memory: Arc<CacheImpl<I, CacheEntry<O>>>,
+    refreshing: Arc<Mutex<HashSet<String>>>,
+}
+
+type SharedState = Arc<dyn Any + Send + Sync>;
+
+static ISOLATE_STATE: OnceLock<Mutex<HashMap<&'static str, SharedState>>> = OnceLock::new();
 
+fn isolate_state<I, O>(
+    prefix: &'static str,
+    config: &ResolverCacheConfig,
+) -> Arc<IsolateState<I, O>>
+where
+    I: Send + Sync + Eq + Hash + 'static,
+    O: Send + Sync + Clone + 'static,
+{
+    let mut states = ISOLATE_STATE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let state = states.entry(prefix).or_insert_with(|| {
+        Arc::new(IsolateState::<I, O> {
+            memory: Arc::new(CacheImpl::new(CacheConfig {
+                max_capacity: Some(config.memory_capacity),
+                time_to_live: Some(config.stale_ttl),
+            })),
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        })
+    });
+
+    state
+        .clone()
+        .downcast()
+        .expect("each cache prefix is used by a single resolver type")
+}
+
+/// Resolver cache with three tiers: isolate memory, then KV, then the network
 pub struct KvStoreCachedResolver<T: Resolver>
 where
     T::Input: Send + Sized,
     T::Output: Send + Sized,
 {
-    pub cache: KvStoreWrapper<T::Input, T::Output>,
-    pub inner: CachedResolver<T>,
+    inner: Arc<T>,
+    memory: Arc<CacheImpl<T::Input, CacheEntry<T::Output>>>,
+    kv: KvStoreWrapper<T::Input, CacheEntry<T::Output>>,
+    config: ResolverCacheConfig,
+    // keys with a background refresh in flight, so a burst of stale hits only refreshes once
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    background: WaitUntil,
 }
 
 impl<R: Resolver> KvStoreCachedResolver<R>
@@ -26,42 +194,226 @@ where
     R::Input: Send + Sized + Eq + Hash + Sync + 'static,
     R::Output: Send + Sized + Clone + Sync + 'static,
 {
-    pub fn new(inner: R, kv: Arc<worker::kv::KvStore>, prefix: &'static str) -> Self {
+    pub fn new(
+        inner: R,
+        kv: Arc<worker::kv::KvStore>,
+        prefix: &'static str,
+        config: ResolverCacheConfig,
+        background: WaitUntil,
+    ) -> Self {
+        let state = isolate_state(prefix, &config);
         KvStoreCachedResolver {
-            inner: CachedResolver::new(
-                inner,
-                CacheImpl::new(CacheConfig {
-                    max_capacity: Some(100),
-                    time_to_live: Some(CACHE_TTL),
-                }),
+            inner: Arc::new(inner),
+            memory: state.memory.clone(),
+            kv: KvStoreWrapper::new(kv, prefix, config.stale_ttl),
+            config,
+            refreshing: state.refreshing.clone(),
+            background,
+        }
+    }
+
+    fn freshness(&self, entry: &CacheEntry<R::Output>) -> Freshness {
+        let age = entry.age();
+        if entry.value.is_none() {
+            // failures are never served stale, once the negative ttl is up we retry
+            return if age < self.config.negative_ttl {
+                Freshness::Fresh
+            } else {
+                Freshness::Expired
+            };
+        }
+
+        if age < self.config.fresh_ttl {
+            Freshness::Fresh
+        } else if age < self.config.stale_ttl {
+            Freshness::Stale
+        } else {
+            Freshness::Expired
+        }
+    }
+}
+
+impl<T> KvStoreCachedResolver<T>
+where
+    T: Resolver + Sync + Send + 'static,
+    T::Error: Send + CacheableError + From<KvStoreError>,
+    T::Input: Send + Sized + Debug + Eq + Hash + Sync + AsRef<str> + Clone + 'static,
+    T::Output: Send + Sized + Debug + Clone + Sync + 'static + Serialize + DeserializeOwned,
+{
+    /// Resolves via the network and writes the result, success or failure, to both tiers
+    async fn fetch(&self, key: &T::Input) -> Result<T::Output, T::Error> {
+        let (entry, result, ttl) = match self.inner.resolve(key).await {
+            Ok(resolved) => (
+                CacheEntry::resolved(resolved.clone()),
+                Ok(resolved),
+                self.config.stale_ttl,
             ),
+            Err(e) => {
+                console_log!("resolving {} failed, caching failure: {}", key.as_ref(), e);
+                (CacheEntry::failed(&e), Err(e), self.config.negative_ttl)
+            }
+        };
+
+        self.memory.set(key.clone(), entry.clone()).await;
+        self.kv.set_with_ttl(key.clone(), entry, ttl).await?;
+
+        result
+    }
+
+    /// Resolves via the network regardless of what's cached, for when we know an entry is out
+    /// of date (eg an identity event said so)
+    pub async fn refresh(&self, key: &T::Input) -> Result<T::Output, T::Error> {
+        self.fetch(key).await
+    }
 
-            cache: KvStoreWrapper::new(kv, prefix, CACHE_TTL),
+    /// The uncached resolver
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Kicks off a refresh of a stale entry without waiting for it. A failed refresh leaves
+    /// the stale entry in place rather than replacing it with a negative one.
+    fn refresh_in_background(&self, key: &T::Input) {
+        let key_str = key.as_ref().to_string();
+        if !self
+            .refreshing
+            .lock()
+            .expect("refresh set lock poisoned")
+            .insert(key_str.clone())
+        {
+            return;
         }
+
+        let (inner, memory, kv, refreshing) = (
+            self.inner.clone(),
+            self.memory.clone(),
+            self.kv.clone(),
+            self.refreshing.clone(),
+        );
+        let key = key.clone();
+
+        self.background.spawn(async move {
+            match inner.resolve(&key).await {
+                Ok(resolved) => {
+                    let entry = CacheEntry::resolved(resolved);
+                    memory.set(key.clone(), entry.clone()).await;
+                    if let Err(e) = kv.set(key.clone(), entry).await {
+                        console_log!("error writing refreshed {} to kv: {}", key_str, e);
+                    }
+                }
+                Err(e) => console_log!("background refresh of {} failed: {}", key_str, e),
+            }
+
+            refreshing
+                .lock()
+                .expect("refresh set lock poisoned")
+                .remove(&key_str);
+        });
+    }
+
+    /// Serves a cache hit, or returns None if the entry is too old to use
+    fn serve(
+        &self,
+        key: &T::Input,
+        entry: CacheEntry<T::Output>,
+    ) -> Option<Result<T::Output, T::Error>> {
+        match self.freshness(&entry) {
+            Freshness::Expired => return None,
+            Freshness::Stale => {
+                console_debug!("serving stale entry for {}", key.as_ref());
+                self.refresh_in_background(key);
+            }
+            Freshness::Fresh => {}
+        }
+
+        Some(match entry.value {
+            Some(value) => Ok(value),
+            None if entry.not_found => Err(CachedFailure::NotFound.into()),
+            None => Err(CachedFailure::Other(entry.error.unwrap_or_default()).into()),
+        })
     }
 }
 
 impl<T> Resolver for KvStoreCachedResolver<T>
 where
     T: Resolver + Sync + Send + 'static,
-    T::Error: Send + From<KvStoreError>,
-    T::Input: Send + Sized + Debug + Eq + Hash + Sync + AsRef<str> + Clone,
+    T::Error: Send + CacheableError + From<KvStoreError>,
+    T::Input: Send + Sized + Debug + Eq + Hash + Sync + AsRef<str> + Clone + 'static,
     T::Output: Send + Sized + Debug + Clone + Sync + 'static + Serialize + DeserializeOwned,
 {
     type Input = T::Input;
     type Output = T::Output;
     type Error = T::Error;
 
-    async fn resolve(&self, handle: &Self::Input) -> Result<Self::Output, Self::Error> {
-        match self.cache.get(handle).await? {
-            Some(cached) => Ok(cached),
-            None => {
-                let resolved = self.inner.resolve(handle).await?;
-
-                self.cache.set(handle.clone(), resolved.clone()).await?;
+    async fn resolve(&self, key: &Self::Input) -> Result<Self::Output, Self::Error> {
+        if let Some(entry) = self.memory.get(key).await {
+            if let Some(result) = self.serve(key, entry) {
+                return result;
+            }
+        }
 
-                Ok(resolved)
+        if let Some(entry) = self.kv.get(key).await? {
+            self.memory.set(key.clone(), entry.clone()).await;
+            if let Some(result) = self.serve(key, entry) {
+                return result;
             }
         }
+
+        self.fetch(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_not_found_is_still_not_found() {
+        let entry: CacheEntry<String> = CacheEntry::failed(&atrium_identity::Error::NotFound);
+        // through KV and back
+        let entry: CacheEntry<String> =
+            serde_json::from_str(&serde_json::to_string(&entry).unwrap()).unwrap();
+        assert!(entry.not_found);
+
+        let error: atrium_identity::Error = CachedFailure::NotFound.into();
+        assert!(matches!(error, atrium_identity::Error::NotFound));
+    }
+
+    #[test]
+    fn other_cached_failures_stay_distinct_from_not_found() {
+        let entry: CacheEntry<String> = CacheEntry::failed(&atrium_identity::Error::HttpStatus(
+            http::StatusCode::BAD_GATEWAY,
+        ));
+        assert!(!entry.not_found);
+
+        let error: atrium_identity::Error = CachedFailure::Other("502".to_string()).into();
+        assert!(!error.is_not_found());
+    }
+
+    #[test]
+    fn isolate_state_outlives_the_resolver_built_from_it() {
+        let config = ResolverCacheConfig {
+            fresh_ttl: Duration::from_secs(1),
+            stale_ttl: Duration::from_secs(2),
+            negative_ttl: Duration::from_secs(1),
+            memory_capacity: 10,
+        };
+
+        let first = isolate_state::<String, String>("test:shared", &config);
+        first.refreshing.lock().unwrap().insert("key".to_string());
+        // what the next request's resolver gets
+        let second = isolate_state::<String, String>("test:shared", &config);
+        assert!(Arc::ptr_eq(&first.memory, &second.memory));
+        assert!(second.refreshing.lock().unwrap().contains("key"));
+
+        let other = isolate_state::<String, String>("test:other", &config);
+        assert!(!Arc::ptr_eq(&first.memory, &other.memory));
+    }
+
+    #[test]
+    fn entries_cached_before_not_found_was_tracked_still_load() {
+        let entry: CacheEntry<String> =
+            serde_json::from_str(r#"{"value":null,"error":"boom","fetched_at_ms":0}"#).unwrap();
+        assert!(!entry.not_found);
     }
 }
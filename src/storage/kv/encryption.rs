@@ -0,0 +1,241 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context as _};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+
+use super::KvStoreError;
+
+/// Prefix marking a KV value as sealed. Anything without it is legacy plaintext JSON.
+const SEALED_PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM keys for values at rest in KV. The first key seals new values, the rest are
+/// only kept around to open values sealed before a rotation.
+pub struct Keyring {
+    current: (String, Aes256Gcm),
+    previous: Vec<(String, Aes256Gcm)>,
+}
+
+/// A decrypted value, plus whether it should be written back under the current key
+pub struct Opened {
+    pub plaintext: String,
+    pub needs_reseal: bool,
+}
+
+impl Keyring {
+    /// Loads keys from the `OAUTH_STORE_KEYS` secret, if set. See [`Keyring::parse`] for the
+    /// format.
+    pub fn from_env(env: &worker::Env) -> anyhow::Result<Option<Self>> {
+        match env.secret("OAUTH_STORE_KEYS") {
+            Ok(secret) => Self::parse(&secret.to_string()).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Parses a comma separated list of `key_id:base64_key` entries, newest first, where each
+    /// key is 32 random bytes (eg `openssl rand -base64 32`). Rotate by prepending a new entry
+    /// and drop old entries once everything has been resealed.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut keys = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (id, key) = entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("key entry must look like key_id:base64_key"))?;
+                if id.is_empty() {
+                    bail!("key id must not be empty");
+                }
+
+                let key = STANDARD
+                    .decode(key)
+                    .with_context(|| format!("key {id} is not valid base64"))?;
+                if key.len() != 32 {
+                    bail!("key {id} must be 32 bytes, got {}", key.len());
+                }
+
+                Ok((
+                    id.to_string(),
+                    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if keys.is_empty() {
+            bail!("no encryption keys configured");
+        }
+
+        let current = keys.remove(0);
+        Ok(Self {
+            current,
+            previous: keys,
+        })
+    }
+
+    /// Encrypts `plaintext`, binding it to `aad` (the KV key) so a sealed value can't be
+    /// copied under some other key and still decrypt
+    pub fn seal(&self, plaintext: &str, aad: &str) -> Result<String, KvStoreError> {
+        let (id, cipher) = &self.current;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| KvStoreError::Encryption("sealing value failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Ok(format!(
+            "{SEALED_PREFIX}{id}:{}",
+            URL_SAFE_NO_PAD.encode(sealed)
+        ))
+    }
+
+    /// Decrypts a value read from KV. Legacy plaintext values are passed through and flagged
+    /// for resealing, as are values sealed with a key other than the current one.
+    pub fn open(&self, stored: String, aad: &str) -> Result<Opened, KvStoreError> {
+        let Some(sealed) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(Opened {
+                plaintext: stored,
+                needs_reseal: true,
+            });
+        };
+
+        let (id, data) = sealed
+            .split_once(':')
+            .ok_or_else(|| KvStoreError::Encryption("malformed sealed value".to_string()))?;
+
+        let (cipher, needs_reseal) = if id == self.current.0 {
+            (&self.current.1, false)
+        } else {
+            let (_, cipher) = self
+                .previous
+                .iter()
+                .find(|(prev, _)| prev == id)
+                .ok_or_else(|| KvStoreError::Encryption(format!("unknown key id {id}")))?;
+            (cipher, true)
+        };
+
+        let data = URL_SAFE_NO_PAD
+            .decode(data)
+            .map_err(|e| KvStoreError::Encryption(format!("malformed sealed value: {e}")))?;
+        if data.len() < NONCE_LEN {
+            return Err(KvStoreError::Encryption(
+                "sealed value too short".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| KvStoreError::Encryption(format!("decrypting value with key {id}")))?;
+
+        Ok(Opened {
+            plaintext: String::from_utf8(plaintext)
+                .map_err(|e| KvStoreError::Encryption(e.to_string()))?,
+            needs_reseal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> String {
+        STANDARD.encode([byte; 32])
+    }
+
+    #[test]
+    fn sealed_values_open_with_the_same_kv_key() {
+        let keyring = Keyring::parse(&format!("k1:{}", key(1))).unwrap();
+        let sealed = keyring
+            .seal(r#"{"session":1}"#, "session:did:plc:a")
+            .unwrap();
+        assert!(sealed.starts_with("enc:k1:"));
+        assert!(!sealed.contains("session"));
+
+        let opened = keyring.open(sealed, "session:did:plc:a").unwrap();
+        assert_eq!(opened.plaintext, r#"{"session":1}"#);
+        assert!(!opened.needs_reseal);
+    }
+
+    #[test]
+    fn values_sealed_before_a_rotation_open_and_need_resealing() {
+        let old = Keyring::parse(&format!("k1:{}", key(1))).unwrap();
+        let sealed = old.seal("secret", "aad").unwrap();
+
+        let rotated = Keyring::parse(&format!("k2:{}, k1:{}", key(2), key(1))).unwrap();
+        let opened = rotated.open(sealed, "aad").unwrap();
+        assert_eq!(opened.plaintext, "secret");
+        assert!(opened.needs_reseal);
+        assert!(rotated
+            .seal("secret", "aad")
+            .unwrap()
+            .starts_with("enc:k2:"));
+    }
+
+    #[test]
+    fn sealed_values_moved_to_another_kv_key_dont_open() {
+        let keyring = Keyring::parse(&format!("k1:{}", key(1))).unwrap();
+        let sealed = keyring.seal("secret", "session:did:plc:a").unwrap();
+        assert!(keyring.open(sealed, "session:did:plc:b").is_err());
+    }
+
+    #[test]
+    fn unknown_key_ids_dont_open() {
+        let sealed = Keyring::parse(&format!("k1:{}", key(1)))
+            .unwrap()
+            .seal("secret", "aad")
+            .unwrap();
+
+        // k1 dropped from the ring too early
+        let keyring = Keyring::parse(&format!("k2:{}", key(2))).unwrap();
+        assert!(keyring.open(sealed.clone(), "aad").is_err());
+
+        // same id, different key material
+        let keyring = Keyring::parse(&format!("k1:{}", key(3))).unwrap();
+        assert!(keyring.open(sealed, "aad").is_err());
+    }
+
+    #[test]
+    fn plaintext_values_pass_through_for_resealing() {
+        let keyring = Keyring::parse(&format!("k1:{}", key(1))).unwrap();
+        let opened = keyring
+            .open(r#"{"legacy":true}"#.to_string(), "aad")
+            .unwrap();
+        assert_eq!(opened.plaintext, r#"{"legacy":true}"#);
+        assert!(opened.needs_reseal);
+
+        assert!(keyring.open("enc:k1".to_string(), "aad").is_err());
+        assert!(keyring.open("enc:k1:AAAA".to_string(), "aad").is_err());
+    }
+
+    #[test]
+    fn malformed_key_specs_are_rejected() {
+        for spec in [
+            "",
+            " , ",
+            &key(1),
+            &format!(":{}", key(1)),
+            "k1:not base64!",
+            &format!("k1:{}", STANDARD.encode([1; 16])),
+            &format!("k1:{},k2", key(1)),
+        ] {
+            assert!(Keyring::parse(spec).is_err(), "accepted {spec:?}");
+        }
+    }
+}
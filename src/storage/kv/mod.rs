@@ -11,14 +11,23 @@ use std::hash::Hash;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::Duration;
+use worker::console_log;
 use worker::kv::{KvError, KvStore};
 use worker::send::SendWrapper;
 
 pub mod cached_resolver;
+pub mod encryption;
+pub mod session_index;
 pub mod session_state;
 
+use encryption::Keyring;
+
 #[derive(Debug)]
-pub struct KvStoreError(SendWrapper<KvError>);
+pub enum KvStoreError {
+    Kv(SendWrapper<KvError>),
+    Encryption(String),
+    Session(String),
+}
 
 impl From<KvStoreError> for atrium_identity::Error {
     fn from(_value: KvStoreError) -> Self {
@@ -28,25 +37,32 @@ impl From<KvStoreError> for atrium_identity::Error {
 
 impl std::error::Error for KvStoreError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        self.0.source()
+        match self {
+            KvStoreError::Kv(e) => e.source(),
+            KvStoreError::Encryption(_) | KvStoreError::Session(_) => None,
+        }
     }
 }
 
 impl Display for KvStoreError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Display::fmt(&self.0, f)
+        match self {
+            KvStoreError::Kv(e) => std::fmt::Display::fmt(&e.0, f),
+            KvStoreError::Encryption(msg) => write!(f, "kv value encryption error: {msg}"),
+            KvStoreError::Session(msg) => write!(f, "session store error: {msg}"),
+        }
     }
 }
 
 impl From<KvError> for KvStoreError {
     fn from(value: KvError) -> Self {
-        Self(SendWrapper(value))
+        Self::Kv(SendWrapper(value))
     }
 }
 
 impl From<serde_json::Error> for KvStoreError {
     fn from(value: serde_json::Error) -> Self {
-        Self(SendWrapper(value.into()))
+        Self::Kv(SendWrapper(value.into()))
     }
 }
 
@@ -55,6 +71,8 @@ pub struct KvStoreWrapper<K, V> {
     inner: SendWrapper<Arc<KvStore>>,
     prefix: &'static str,
     expiration_ttl: Duration,
+    // when set, values are sealed before they're written and opened after they're read
+    keyring: Option<Arc<Keyring>>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -64,9 +82,17 @@ impl<K, V> KvStoreWrapper<K, V> {
             inner: SendWrapper(inner),
             expiration_ttl,
             prefix,
+            keyring: None,
             _phantom: PhantomData,
         }
     }
+
+    /// Encrypt values at rest. Existing plaintext values stay readable and are resealed the
+    /// first time they're read.
+    pub fn with_keyring(mut self, keyring: Option<Arc<Keyring>>) -> Self {
+        self.keyring = keyring;
+        self
+    }
 }
 
 impl<K, V> Debug for KvStoreWrapper<K, V> {
@@ -75,6 +101,41 @@ impl<K, V> Debug for KvStoreWrapper<K, V> {
     }
 }
 
+// KV rejects expiration TTLs shorter than this
+const MIN_EXPIRATION_TTL: Duration = Duration::new(60, 0);
+
+impl<K, V> KvStoreWrapper<K, V>
+where
+    K: AsRef<str>,
+    V: Serialize,
+{
+    /// Like `Store::set`, but with a per-entry expiration instead of the wrapper's default
+    #[worker::send]
+    pub async fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Result<(), KvStoreError> {
+        let key = format!("{}:{}", self.prefix, key.as_ref());
+        // NOTE: manually converting this to a string w/ serde fixed a weird bug I was seeing,
+        //       in theory it wouldn't be needed and I could just call put directly but :shrug_emoji:
+        self.put_raw(&key, serde_json::to_string(&value)?, ttl)
+            .await
+    }
+}
+
+impl<K, V> KvStoreWrapper<K, V> {
+    async fn put_raw(&self, key: &str, value: String, ttl: Duration) -> Result<(), KvStoreError> {
+        let value = match &self.keyring {
+            Some(keyring) => keyring.seal(&value, key)?,
+            None => value,
+        };
+
+        self.inner
+            .put(key, value)?
+            .expiration_ttl(ttl.max(MIN_EXPIRATION_TTL).as_secs())
+            .execute()
+            .await?;
+        Ok(())
+    }
+}
+
 pub type KvSessionStore = KvStoreWrapper<Did, Session>;
 impl SessionStore for KvSessionStore {}
 
@@ -91,25 +152,31 @@ where
     #[worker::send]
     async fn get(&self, key: &K) -> Result<Option<V>, Self::Error> {
         let key = format!("{}:{}", self.prefix, key.as_ref());
-        let s = self.inner.get(&key).text().await?;
+        let s = match self.inner.get(&key).text().await? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let s = match &self.keyring {
+            Some(keyring) => {
+                let opened = keyring.open(s, &key)?;
+                if opened.needs_reseal {
+                    // plaintext from before encryption was enabled, or sealed with a rotated
+                    // out key. this resets the entry's expiry, which is fine for our ttls
+                    console_log!("resealing {} under the current key", &key);
+                    self.put_raw(&key, opened.plaintext.clone(), self.expiration_ttl)
+                        .await?;
+                }
+                opened.plaintext
+            }
+            None => s,
+        };
 
-        match s {
-            Some(s) => Ok(Some(serde_json::from_str(&s)?)),
-            None => Ok(None),
-        }
+        Ok(Some(serde_json::from_str(&s)?))
     }
 
-    #[worker::send]
     async fn set(&self, key: K, value: V) -> Result<(), Self::Error> {
-        let key = format!("{}:{}", self.prefix, key.as_ref());
-        // NOTE: manually converting this to a string w/ serde fixed a weird bug I was seeing,
-        //       in theory it wouldn't be needed and I could just call put directly but :shrug_emoji:
-        self.inner
-            .put(&key, serde_json::to_string(&value)?)?
-            .expiration_ttl(self.expiration_ttl.as_secs())
-            .execute()
-            .await?;
-        Ok(())
+        self.set_with_ttl(key, value, self.expiration_ttl).await
     }
 
     #[worker::send]
@@ -0,0 +1,157 @@
+use std::str::FromStr as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+use atrium_api::types::string::Did;
+use atrium_common::store::Store as _;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tower_sessions::session::Id;
+use tower_sessions::SessionStore as _;
+use worker::kv::KvStore;
+
+use super::session_state::KvTowerSessionStore;
+use super::{KvStoreError, KvStoreWrapper};
+
+// last-seen only needs to be roughly right, so don't rewrite the index on every request
+const TOUCH_INTERVAL: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+/// A logged in browser session, as listed to its owner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveSession {
+    /// opaque handle used to refer to this session in the UI. the session ID itself is the
+    /// cookie secret, so it never leaves the server
+    pub handle: String,
+    session_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub user_agent: Option<String>,
+}
+
+impl ActiveSession {
+    pub fn is(&self, session_id: &Id) -> bool {
+        self.session_id == session_id.to_string()
+    }
+}
+
+/// Per-DID index of tower sessions, so a user's sessions can be listed and revoked
+#[derive(Clone)]
+pub struct SessionIndex {
+    index: KvStoreWrapper<Did, Vec<ActiveSession>>,
+    sessions: KvTowerSessionStore,
+    session_ttl: Duration,
+}
+
+impl SessionIndex {
+    pub fn new(kv: Arc<KvStore>, sessions: KvTowerSessionStore, session_ttl: Duration) -> Self {
+        Self {
+            index: KvStoreWrapper::new(kv, "tower:session-index", session_ttl),
+            sessions,
+            session_ttl,
+        }
+    }
+
+    /// Lists a DID's sessions, most recently seen first. Sessions idle for longer than the
+    /// session store TTL have expired out of KV already and are skipped.
+    pub async fn list(&self, did: &Did) -> Result<Vec<ActiveSession>, KvStoreError> {
+        let ttl = chrono::TimeDelta::from_std(self.session_ttl).unwrap_or(chrono::TimeDelta::MAX);
+        let now = Utc::now();
+
+        let mut sessions: Vec<_> = self
+            .index
+            .get(did)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| now - s.last_seen < ttl)
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+
+        Ok(sessions)
+    }
+
+    /// Adds a freshly logged in session to the DID's index
+    pub async fn register(
+        &self,
+        did: &Did,
+        session_id: Id,
+        user_agent: Option<String>,
+    ) -> Result<(), KvStoreError> {
+        let mut sessions = self.list(did).await?;
+        let now = Utc::now();
+
+        sessions.push(ActiveSession {
+            handle: Id::default().to_string(),
+            session_id: session_id.to_string(),
+            created_at: now,
+            last_seen: now,
+            user_agent,
+        });
+
+        self.index.set(did.clone(), sessions).await
+    }
+
+    /// Bumps last-seen for a session, at most once every few minutes
+    pub async fn touch(&self, did: &Did, session_id: Id) -> Result<(), KvStoreError> {
+        let mut sessions = self.list(did).await?;
+        let now = Utc::now();
+
+        match sessions.iter_mut().find(|s| s.is(&session_id)) {
+            Some(s) if now - s.last_seen > TOUCH_INTERVAL => s.last_seen = now,
+            _ => return Ok(()),
+        }
+
+        self.index.set(did.clone(), sessions).await
+    }
+
+    /// Deletes one session, returning how many sessions the DID has left
+    pub async fn revoke(&self, did: &Did, handle: &str) -> Result<usize, KvStoreError> {
+        let sessions = self.list(did).await?;
+        let (revoked, remaining): (Vec<_>, Vec<_>) =
+            sessions.into_iter().partition(|s| s.handle == handle);
+
+        for session in revoked.iter() {
+            self.delete_session(session).await?;
+        }
+        let left = remaining.len();
+        self.index.set(did.clone(), remaining).await?;
+
+        Ok(left)
+    }
+
+    /// Like `revoke`, but by session ID, for when a user logs out of their current session
+    pub async fn forget(&self, did: &Did, session_id: Id) -> Result<usize, KvStoreError> {
+        let handle = self
+            .list(did)
+            .await?
+            .into_iter()
+            .find(|s| s.is(&session_id))
+            .map(|s| s.handle);
+
+        match handle {
+            Some(handle) => self.revoke(did, &handle).await,
+            None => Ok(self.list(did).await?.len()),
+        }
+    }
+
+    /// Deletes every session belonging to a DID
+    pub async fn revoke_all(&self, did: &Did) -> Result<(), KvStoreError> {
+        for session in self.list(did).await?.iter() {
+            self.delete_session(session).await?;
+        }
+
+        self.index.del(did).await
+    }
+
+    async fn delete_session(&self, session: &ActiveSession) -> Result<(), KvStoreError> {
+        // a malformed id can't refer to a live session, so there's nothing to delete
+        let Ok(id) = Id::from_str(&session.session_id) else {
+            return Ok(());
+        };
+
+        self.sessions
+            .delete(&id)
+            .await
+            .map_err(|e| KvStoreError::Session(e.to_string()))
+    }
+}
@@ -10,8 +10,8 @@ pub type KvTowerSessionStore = KvStoreWrapper<String, Record>;
 #[async_trait]
 impl tower_sessions::SessionStore for KvTowerSessionStore {
     async fn create(&self, record: &mut Record) -> Result<()> {
-        let mut record = record.clone();
-
+        // NOTE: mutate the caller's record so a regenerated ID is the one that ends up in the
+        //       session cookie
         while self
             .get(&record.id.to_string())
             .await
@@ -0,0 +1,411 @@
+use atrium_api::types::string::Did;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use worker::d1::serde_wasm_bindgen;
+use worker::wasm_bindgen::JsValue;
+
+use super::db::{NOT_EXPIRED, STATUS_WITH_HANDLE};
+use crate::types::status::StatusFromDb;
+
+/// Upper bound on page size, regardless of what the caller asks for
+pub const MAX_PAGE_SIZE: usize = 100;
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Which timestamp column a search is ordered and paginated by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeField {
+    #[default]
+    Created,
+    Indexed,
+}
+
+impl TimeField {
+    fn column(self) -> &'static str {
+        match self {
+            TimeField::Created => "createdAt",
+            TimeField::Indexed => "indexedAt",
+        }
+    }
+
+    fn of(self, status: &StatusFromDb) -> DateTime<Utc> {
+        match self {
+            TimeField::Created => status.created_at,
+            TimeField::Indexed => status.indexed_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+/// Opaque keyset pagination cursor: the sort timestamp and uri of the last row on a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    timestamp: DateTime<Utc>,
+    uri: String,
+}
+
+impl Cursor {
+    fn after(sort_by: TimeField, status: &StatusFromDb) -> Self {
+        Self {
+            timestamp: sort_by.of(status),
+            uri: status.uri.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}::{}", self.timestamp.timestamp_micros(), self.uri)
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (micros, uri) = s
+            .split_once("::")
+            .ok_or_else(|| format!("malformed cursor: {s}"))?;
+        let timestamp = micros
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(|| format!("malformed cursor timestamp: {micros}"))?;
+
+        Ok(Self {
+            timestamp,
+            uri: uri.to_string(),
+        })
+    }
+}
+
+/// Builder for filtered, paginated reads of the status table
+#[derive(Debug, Clone, Default)]
+pub struct StatusQuery {
+    emoji: Option<String>,
+    author: Option<Did>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    indexed_after: Option<DateTime<Utc>>,
+    indexed_before: Option<DateTime<Utc>>,
+    sort_by: TimeField,
+    order: SortOrder,
+    limit: Option<usize>,
+    cursor: Option<Cursor>,
+    include_expired: bool,
+}
+
+/// One page of query results plus the cursor for the next page, if there is one
+#[derive(Debug, Clone)]
+pub struct StatusPage {
+    pub statuses: Vec<StatusFromDb>,
+    pub cursor: Option<Cursor>,
+}
+
+impl StatusQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn emoji(mut self, emoji: impl Into<String>) -> Self {
+        self.emoji = Some(emoji.into());
+        self
+    }
+
+    pub fn author(mut self, did: Did) -> Self {
+        self.author = Some(did);
+        self
+    }
+
+    /// inclusive lower bound on createdAt
+    pub fn created_after(mut self, t: DateTime<Utc>) -> Self {
+        self.created_after = Some(t);
+        self
+    }
+
+    /// exclusive upper bound on createdAt
+    pub fn created_before(mut self, t: DateTime<Utc>) -> Self {
+        self.created_before = Some(t);
+        self
+    }
+
+    /// inclusive lower bound on indexedAt
+    pub fn indexed_after(mut self, t: DateTime<Utc>) -> Self {
+        self.indexed_after = Some(t);
+        self
+    }
+
+    /// exclusive upper bound on indexedAt
+    pub fn indexed_before(mut self, t: DateTime<Utc>) -> Self {
+        self.indexed_before = Some(t);
+        self
+    }
+
+    pub fn sort_by(mut self, field: TimeField) -> Self {
+        self.sort_by = field;
+        self
+    }
+
+    pub fn order(mut self, order: SortOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    pub fn cursor(mut self, cursor: Cursor) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// also return statuses past their expiresAt, which are hidden by default
+    pub fn include_expired(mut self) -> Self {
+        self.include_expired = true;
+        self
+    }
+
+    pub(super) fn page_size(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Builds the SQL and bind parameters for this query. One row more than the page size is
+    /// requested so we can tell whether a next page exists.
+    pub(super) fn to_sql(&self) -> worker::Result<(String, Vec<JsValue>)> {
+        let (sql, params) = self.sql();
+        let binds = params.iter().map(bind).collect::<worker::Result<_>>()?;
+        Ok((sql, binds))
+    }
+
+    // timestamps are compared and ordered as dates, like the upserts in db.rs do: their text
+    // forms don't all have the same precision, so comparing them as strings puts
+    // `...:00.5Z` after `...:00.25Z` and after `...:00Z` alike
+    fn sql(&self) -> (String, Vec<Param>) {
+        let mut clauses: Vec<String> = Vec::new();
+        let mut binds = Vec::new();
+
+        if let Some(emoji) = &self.emoji {
+            clauses.push("status = ?".to_string());
+            binds.push(Param::Text(emoji.clone()));
+        }
+        if let Some(author) = &self.author {
+            clauses.push("authorDid = ?".to_string());
+            binds.push(Param::Text(author.to_string()));
+        }
+
+        if !self.include_expired {
+            clauses.push(NOT_EXPIRED.to_string());
+        }
+
+        let ranges = [
+            ("julianday(createdAt) >= julianday(?)", &self.created_after),
+            ("julianday(createdAt) < julianday(?)", &self.created_before),
+            ("julianday(indexedAt) >= julianday(?)", &self.indexed_after),
+            ("julianday(indexedAt) < julianday(?)", &self.indexed_before),
+        ];
+        for (clause, bound) in ranges {
+            if let Some(t) = bound {
+                clauses.push(clause.to_string());
+                binds.push(Param::timestamp(t));
+            }
+        }
+
+        let column = format!("julianday({})", self.sort_by.column());
+        let (cmp, direction) = match self.order {
+            SortOrder::Newest => ("<", "DESC"),
+            SortOrder::Oldest => (">", "ASC"),
+        };
+
+        if let Some(cursor) = &self.cursor {
+            clauses.push(format!(
+                "({column} {cmp} julianday(?) OR ({column} = julianday(?) AND uri {cmp} ?))"
+            ));
+            binds.push(Param::timestamp(&cursor.timestamp));
+            binds.push(Param::timestamp(&cursor.timestamp));
+            binds.push(Param::Text(cursor.uri.clone()));
+        }
+
+        let filter = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+
+        let sql = format!(
+            "{STATUS_WITH_HANDLE} {filter} ORDER BY {column} {direction}, uri {direction} LIMIT ?"
+        );
+        binds.push(Param::Integer(self.page_size() + 1));
+
+        (sql, binds)
+    }
+
+    /// Splits off the lookahead row fetched by `to_sql`, producing the next-page cursor
+    pub(super) fn paginate(&self, mut statuses: Vec<StatusFromDb>) -> StatusPage {
+        let cursor = if statuses.len() > self.page_size() {
+            statuses.truncate(self.page_size());
+            statuses.last().map(|s| Cursor::after(self.sort_by, s))
+        } else {
+            None
+        };
+
+        StatusPage { statuses, cursor }
+    }
+}
+
+/// A bind parameter, kept as plain data until it's handed to D1
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+enum Param {
+    Text(String),
+    Integer(usize),
+}
+
+impl Param {
+    // the same text `worker::query!` stores a DateTime as
+    fn timestamp(t: &DateTime<Utc>) -> Self {
+        Param::Text(t.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+    }
+}
+
+// serialize bind parameters the same way `worker::query!` does
+fn bind<T: Serialize + ?Sized>(value: &T) -> worker::Result<JsValue> {
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_missing_as_null(true);
+    value
+        .serialize(&serializer)
+        .map_err(|e| worker::Error::Internal(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::{Value, ValueRef};
+    use rusqlite::Connection;
+
+    use super::*;
+
+    fn migrated_db() -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let mut migrations: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        migrations.sort();
+        for migration in migrations {
+            db.execute_batch(&std::fs::read_to_string(migration).unwrap())
+                .unwrap();
+        }
+        db
+    }
+
+    fn insert(db: &Connection, uri: &str, created_at: &str) {
+        db.execute(
+            "INSERT INTO status (uri, authorDid, status, createdAt, indexedAt, seenOnJetstream, createdViaThisApp)
+             VALUES (?1, 'did:plc:ewvi7nxzyoun6zhxrhs64oiz', '👍', ?2, ?2, 1, 0)",
+            (uri, created_at),
+        )
+        .unwrap();
+    }
+
+    /// Runs a query the way `StatusDb::search` does, against SQLite
+    fn search(db: &Connection, query: &StatusQuery) -> StatusPage {
+        let (sql, params) = query.sql();
+        let params: Vec<Value> = params
+            .into_iter()
+            .map(|p| match p {
+                Param::Text(s) => Value::Text(s),
+                Param::Integer(n) => Value::Integer(n as i64),
+            })
+            .collect();
+
+        let mut stmt = db.prepare(&sql).unwrap();
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+        let statuses = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let mut object = serde_json::Map::new();
+                for (i, column) in columns.iter().enumerate() {
+                    let value = match row.get_ref(i)? {
+                        ValueRef::Null => serde_json::Value::Null,
+                        ValueRef::Integer(n) => n.into(),
+                        ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+                        other => panic!("unexpected column value {other:?}"),
+                    };
+                    object.insert(column.clone(), value);
+                }
+                Ok(serde_json::from_value::<StatusFromDb>(object.into()).unwrap())
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        query.paginate(statuses)
+    }
+
+    fn all_pages(db: &Connection, query: StatusQuery) -> Vec<String> {
+        let mut uris = Vec::new();
+        let mut page = search(db, &query);
+        loop {
+            uris.extend(page.statuses.iter().map(|s| s.uri.clone()));
+            let Some(cursor) = page.cursor else {
+                return uris;
+            };
+            page = search(db, &query.clone().cursor(cursor));
+        }
+    }
+
+    #[test]
+    fn pages_through_rows_sharing_a_timestamp() {
+        let db = migrated_db();
+        // the same instant written with different precision, the way different clients and
+        // chrono's own serializer do
+        insert(&db, "at://a/1", "2026-10-01T12:00:00Z");
+        insert(&db, "at://a/2", "2026-10-01T12:00:00.000Z");
+        insert(&db, "at://a/3", "2026-10-01T12:00:00.000000Z");
+        insert(&db, "at://a/4", "2026-10-01T12:00:00Z");
+        insert(&db, "at://a/5", "2026-10-01T12:00:00.000Z");
+        // as text these sort after every row above, and .5 before .25
+        insert(&db, "at://b/1", "2026-10-01T12:00:00.5Z");
+        insert(&db, "at://b/2", "2026-10-01T12:00:00.25Z");
+        insert(&db, "at://c/1", "2026-10-01T11:59:59Z");
+
+        let newest = all_pages(&db, StatusQuery::new().limit(2));
+        assert_eq!(
+            newest,
+            [
+                "at://b/1", "at://b/2", "at://a/5", "at://a/4", "at://a/3", "at://a/2", "at://a/1",
+                "at://c/1"
+            ]
+        );
+
+        let mut oldest = all_pages(&db, StatusQuery::new().order(SortOrder::Oldest).limit(3));
+        oldest.reverse();
+        assert_eq!(oldest, newest);
+    }
+
+    #[test]
+    fn range_filters_compare_as_dates() {
+        let db = migrated_db();
+        insert(&db, "at://a/1", "2026-10-01T12:00:00Z");
+        insert(&db, "at://a/2", "2026-10-01T12:00:00.250Z");
+        insert(&db, "at://a/3", "2026-10-01T12:00:01Z");
+
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let uris = all_pages(
+            &db,
+            StatusQuery::new()
+                .created_after(at("2026-10-01T12:00:00.000Z"))
+                .created_before(at("2026-10-01T12:00:01.000Z")),
+        );
+        assert_eq!(uris, ["at://a/2", "at://a/1"]);
+    }
+}
@@ -1,5 +1,11 @@
 use anyhow::anyhow;
-use axum::{http::StatusCode, response::IntoResponse};
+use askama::Template as _;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+
+use super::templates::LoginTemplate;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
@@ -13,12 +19,28 @@ pub enum AppError {
     SessionManagement(#[from] tower_sessions::session::Error),
     #[error("Something went wrong in the Oauth flow: {0}")]
     Oauth(#[from] atrium_oauth::Error),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("too many requests, try again in {}s", retry_after_secs(.0))]
+    RateLimited(std::time::Duration),
     #[error("authorization required")]
     NoSessionAuth,
     #[error("admin endpoint - authorization required")]
     NoAdminAuth,
+    #[error("export endpoint - bearer token required")]
+    NoExportAuth,
     #[error("authentication error, maybe your session is invalid")]
     AuthenticationInvalid,
+    #[error("login failed: {0}")]
+    Login(#[from] LoginError),
+    #[error("your session is missing permissions this app needs ({}), re-authorize at /oauth/reconsent", .0.join(" "))]
+    InsufficientScope(Vec<String>),
+    #[error("database schema is at version {applied} but this build requires version {required}, apply pending migrations with `npm run db:migrations:apply`")]
+    SchemaOutdated { applied: u32, required: u32 },
 }
 
 impl<T: std::fmt::Debug> From<atrium_xrpc::Error<T>> for AppError {
@@ -32,9 +54,37 @@ impl<T: std::fmt::Debug> From<atrium_xrpc::Error<T>> for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        if let AppError::Login(e) = self {
+            return e.into_response();
+        }
+        if let AppError::RateLimited(retry_after) = self {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    http::header::RETRY_AFTER,
+                    retry_after_secs(&retry_after).to_string(),
+                )],
+                format!("Error: {self}"),
+            )
+                .into_response();
+        }
+        if let AppError::NoExportAuth = self {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(http::header::WWW_AUTHENTICATE, "Bearer")],
+                format!("Error: {self}"),
+            )
+                .into_response();
+        }
+
         (
             match &self {
                 AppError::NoAdminAuth | AppError::NoSessionAuth => StatusCode::UNAUTHORIZED,
+                AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+                AppError::NotFound(_) => StatusCode::NOT_FOUND,
+                AppError::Conflict(_) => StatusCode::CONFLICT,
+                AppError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+                AppError::SchemaOutdated { .. } => StatusCode::SERVICE_UNAVAILABLE,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             format!("Error: {self}"),
@@ -42,3 +92,117 @@ impl IntoResponse for AppError {
             .into_response()
     }
 }
+
+// Retry-After only does whole seconds, round up so clients don't come back too early
+fn retry_after_secs(d: &std::time::Duration) -> u64 {
+    d.as_millis().div_ceil(1000).max(1) as u64
+}
+
+/// Why a login attempt didn't work out. These are rendered as the login form with the reason
+/// and a retry, rather than a bare error page.
+#[derive(thiserror::Error, Debug)]
+pub enum LoginError {
+    #[error("{reason}")]
+    InvalidIdentifier { input: String, reason: String },
+    #[error("You declined to let Statusphere access your account.")]
+    Denied,
+    #[error("Your account's server couldn't complete the login ({code}{}).", .description.as_ref().map(|d| format!(": {d}")).unwrap_or_default())]
+    AuthorizationServer {
+        code: String,
+        description: Option<String>,
+    },
+    #[error("This login link has expired or was already used. Please log in again.")]
+    StateExpired,
+    #[error("The login response was malformed: {0}")]
+    Malformed(String),
+    #[error("We couldn't find an account for {0}. Check the spelling and try again.")]
+    IdentityNotFound(String),
+    #[error("We couldn't reach your account's server: {0}")]
+    Upstream(String),
+}
+
+impl LoginError {
+    /// Sorts an atrium error from `authorize` or `callback` into something we can explain to
+    /// the user. Anything that isn't the user's or their server's doing stays an internal
+    /// error.
+    pub fn from_oauth(e: atrium_oauth::Error, input: Option<&str>) -> AppError {
+        use atrium_oauth::Error;
+
+        let login_error = match e {
+            // expired state is caught before atrium sees the callback, see
+            // `OAuthClient::callback`
+            Error::Callback(msg) => LoginError::Malformed(msg),
+            Error::Identity(atrium_identity::Error::NotFound) => {
+                LoginError::IdentityNotFound(input.unwrap_or("that account").to_string())
+            }
+            Error::Identity(e) => LoginError::Upstream(e.to_string()),
+            Error::ServerAgent(e) => LoginError::Upstream(e.to_string()),
+            e => return AppError::Oauth(e),
+        };
+
+        AppError::Login(login_error)
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LoginError::Denied => StatusCode::FORBIDDEN,
+            LoginError::InvalidIdentifier { .. }
+            | LoginError::AuthorizationServer { .. }
+            | LoginError::Malformed(_) => StatusCode::BAD_REQUEST,
+            LoginError::StateExpired => StatusCode::GONE,
+            LoginError::IdentityNotFound(_) => StatusCode::NOT_FOUND,
+            LoginError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> axum::response::Response {
+        let identifier = match &self {
+            LoginError::IdentityNotFound(input) | LoginError::InvalidIdentifier { input, .. } => {
+                input.clone()
+            }
+            _ => String::new(),
+        };
+        let page = LoginTemplate {
+            error: self.to_string(),
+            identifier,
+        };
+
+        match page.render() {
+            Ok(html) => (self.status_code(), Html::from(html)).into_response(),
+            Err(_) => (self.status_code(), format!("Error: {self}")).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_auth_failures_ask_for_a_bearer_token() {
+        let response = AppError::NoExportAuth.into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[http::header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[test]
+    fn login_errors_are_sorted_by_variant() {
+        use atrium_oauth::Error;
+
+        assert!(matches!(
+            LoginError::from_oauth(Error::Identity(atrium_identity::Error::NotFound), Some("a.test")),
+            AppError::Login(LoginError::IdentityNotFound(input)) if input == "a.test"
+        ));
+        assert!(matches!(
+            LoginError::from_oauth(Error::Callback("missing `iss` parameter".into()), None),
+            AppError::Login(LoginError::Malformed(_))
+        ));
+        // not the user's doing, so not explained to them as such
+        assert!(matches!(
+            LoginError::from_oauth(Error::StateStore("kv is down".into()), None),
+            AppError::Oauth(_)
+        ));
+    }
+}
@@ -24,29 +24,11 @@ pub struct Event<T> {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Identity {
     did: String,
-    handle: Option<String>,
+    pub handle: Option<String>,
     seq: u64,
     time: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum AccountStatus {
-    TakenDown,
-    Suspended,
-    Deleted,
-    Activated,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Account {
-    did: String,
-    handle: String,
-    seq: u64,
-    time: String,
-    status: AccountStatus,
-}
-
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Commit<T> {
@@ -116,3 +98,20 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_events_parse_whatever_collections_are_wanted() {
+        // jetstream sends these to every subscriber, usually without a handle or status
+        let event: Event<serde_json::Value> = serde_json::from_str(
+            r#"{"did":"did:plc:ewvi7nxzyoun6zhxrhs64oiz","time_us":1725516665333808,"kind":"account","account":{"active":false,"did":"did:plc:ewvi7nxzyoun6zhxrhs64oiz","seq":1409753013,"time":"2024-09-05T06:11:04.870Z","status":"deactivated"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(event.kind, Kind::Account);
+        assert!(event.commit.is_none());
+    }
+}
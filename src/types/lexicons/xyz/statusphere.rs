@@ -1,5 +1,9 @@
 // @generated - This file is generated by esquema-codegen (forked from atrium-codegen). DO NOT EDIT.
 //!Definitions for the `xyz.statusphere` namespace.
+pub mod defs;
+pub mod get_actor_statuses;
+pub mod get_status;
+pub mod get_statuses;
 pub mod status;
 #[derive(Debug)]
 pub struct Status;
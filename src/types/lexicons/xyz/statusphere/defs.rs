@@ -0,0 +1,26 @@
+//!Definitions for the `xyz.statusphere.defs` namespace.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActorViewData {
+    pub did: atrium_api::types::string::Did,
+    ///Only present when the handle resolves back to the DID.
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub handle: core::option::Option<atrium_api::types::string::Handle>,
+}
+pub type ActorView = atrium_api::types::Object<ActorViewData>;
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusViewData {
+    pub author: ActorView,
+    pub created_at: atrium_api::types::string::Datetime,
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub expires_at: core::option::Option<atrium_api::types::string::Datetime>,
+    pub indexed_at: atrium_api::types::string::Datetime,
+    pub status: String,
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub text: core::option::Option<String>,
+    pub uri: String,
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub url: core::option::Option<String>,
+}
+pub type StatusView = atrium_api::types::Object<StatusViewData>;
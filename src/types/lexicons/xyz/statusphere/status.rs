@@ -5,7 +5,16 @@ use atrium_api::types::TryFromUnknown;
 #[serde(rename_all = "camelCase")]
 pub struct RecordData {
     pub created_at: atrium_api::types::string::Datetime,
+    ///Optional time after which the status should no longer be shown.
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub expires_at: core::option::Option<atrium_api::types::string::Datetime>,
     pub status: String,
+    ///Optional short message shown next to the status.
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub text: core::option::Option<String>,
+    ///Optional link to go with the status.
+    #[serde(skip_serializing_if = "core::option::Option::is_none")]
+    pub url: core::option::Option<String>,
 }
 pub type Record = atrium_api::types::Object<RecordData>;
 impl From<atrium_api::types::Unknown> for RecordData {
@@ -1,7 +1,12 @@
-use atrium_api::types::string::Did;
+use atrium_api::types::string::{Datetime, Did, Handle};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use unicode_segmentation::UnicodeSegmentation as _;
+
+use crate::services::resolvers::ActorHandle;
+use crate::types::lexicons::xyz::statusphere::defs::{ActorViewData, StatusViewData};
+use crate::types::lexicons::xyz::statusphere::status;
 
 ///Status table datatype
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -14,6 +19,115 @@ pub struct StatusWithHandle {
     pub seen_on_jetstream: bool,
     pub created_via_this_app: bool,
     pub handle: Option<String>,
+    /// whether `handle` resolves back to `author_did`. unverified handles are only a claim
+    /// made by the DID document and shouldn't be shown as the author's identity
+    #[serde(default)]
+    pub handle_verified: bool,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// the Bluesky post this status was shared as, if any
+    #[serde(default)]
+    pub crosspost_uri: Option<String>,
+}
+
+/// The optional parts of a status beyond the emoji
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StatusDetails {
+    pub text: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub url: Option<String>,
+}
+
+// limits from lexicons/status.json. lexicon maxLength counts UTF-8 bytes
+const MAX_STATUS_GRAPHEMES: usize = 1;
+const MAX_STATUS_BYTES: usize = 32;
+const MAX_TEXT_GRAPHEMES: usize = 100;
+const MAX_TEXT_BYTES: usize = 1000;
+const MAX_URL_BYTES: usize = 2048;
+
+fn text_within_limits(text: &str) -> bool {
+    text.len() <= MAX_TEXT_BYTES && text.graphemes(true).count() <= MAX_TEXT_GRAPHEMES
+}
+
+/// Checks a record against the lexicon's length limits. Jetstream passes records through
+/// unvalidated, so anything can show up from other people's repos.
+pub fn check_record_limits(record: &status::RecordData) -> Result<(), String> {
+    check_status_limits(&record.status)?;
+    if record
+        .text
+        .as_deref()
+        .is_some_and(|t| !text_within_limits(t))
+    {
+        return Err(format!(
+            "text is over {MAX_TEXT_GRAPHEMES} graphemes or {MAX_TEXT_BYTES} bytes"
+        ));
+    }
+    if record.url.as_ref().is_some_and(|u| u.len() > MAX_URL_BYTES) {
+        return Err(format!("url is over {MAX_URL_BYTES} bytes"));
+    }
+    Ok(())
+}
+
+/// Checks the status emoji against the lexicon's length limits
+pub fn check_status_limits(status: &str) -> Result<(), String> {
+    if status.is_empty()
+        || status.len() > MAX_STATUS_BYTES
+        || status.graphemes(true).count() > MAX_STATUS_GRAPHEMES
+    {
+        return Err(format!(
+            "status must be a single grapheme of at most {MAX_STATUS_BYTES} bytes"
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a link is safe to render as an href: http(s) only, so records written by other
+/// clients can't smuggle in javascript: URLs
+pub fn is_web_url(link: &str) -> bool {
+    link.len() <= MAX_URL_BYTES
+        && url::Url::parse(link).is_ok_and(|u| matches!(u.scheme(), "http" | "https"))
+}
+
+impl StatusDetails {
+    /// Checks the details against the lexicon before they're written anywhere, normalizing
+    /// blank fields to None
+    pub fn validated(self) -> Result<Self, String> {
+        let text = self
+            .text
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty());
+        if let Some(text) = &text {
+            if !text_within_limits(text) {
+                return Err(format!(
+                    "status text can be at most {MAX_TEXT_GRAPHEMES} characters"
+                ));
+            }
+        }
+
+        let url = self
+            .url
+            .map(|u| u.trim().to_string())
+            .filter(|u| !u.is_empty());
+        if let Some(link) = &url {
+            if !is_web_url(link) {
+                return Err(format!("{link} must be an http(s) URL"));
+            }
+        }
+
+        if self.expires_at.is_some_and(|t| t <= Utc::now()) {
+            return Err("expiry must be in the future".to_string());
+        }
+
+        Ok(Self {
+            text,
+            expires_at: self.expires_at,
+            url,
+        })
+    }
 }
 
 ///this is what we write to the db
@@ -27,6 +141,10 @@ pub struct Status {
     pub created_at: DateTime<Utc>,
     #[serde(rename = "indexedAt")]
     pub indexed_at: DateTime<Utc>,
+    pub text: Option<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub url: Option<String>,
 }
 
 ///this is what we read from the db
@@ -44,11 +162,30 @@ pub struct StatusFromDb {
     pub seen_on_jetstream: usize, // janky hax, it's stored as a number in sql...
     #[serde(rename = "createdViaThisApp")]
     pub created_via_this_app: usize, // janky hax, it's stored as a number in sql...
+    /// the author's handle from the actor_handle table, None if we haven't stored one yet
+    #[serde(default)]
+    pub handle: Option<String>,
+    #[serde(rename = "handleVerified", default)]
+    pub handle_verified: Option<usize>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(rename = "expiresAt", default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(rename = "crosspostUri", default)]
+    pub crosspost_uri: Option<String>,
+}
+
+/// Sent to websocket clients when a status is deleted, so they can drop it from the feed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusDeleted {
+    pub deleted: String,
 }
 
 //Status methods
 impl Status {
-    pub fn new(uri: String, author_did: Did, status: String) -> Self {
+    pub fn new(uri: String, author_did: Did, status: String, details: StatusDetails) -> Self {
         let now = chrono::Utc::now();
         Self {
             uri,
@@ -56,6 +193,9 @@ impl Status {
             status,
             created_at: now,
             indexed_at: now,
+            text: details.text,
+            expires_at: details.expires_at,
+            url: details.url,
         }
     }
 }
@@ -70,11 +210,48 @@ impl From<StatusFromDb> for StatusWithHandle {
             indexed_at: value.indexed_at,
             seen_on_jetstream: value.seen_on_jetstream != 0,
             created_via_this_app: value.created_via_this_app != 0,
-            handle: None,
+            handle: value.handle.map(|h| format!("@{h}")),
+            handle_verified: value.handle_verified.is_some_and(|v| v != 0),
+            text: value.text,
+            expires_at: value.expires_at,
+            url: value.url,
+            crosspost_uri: value.crosspost_uri,
         }
     }
 }
 
+impl From<StatusFromDb> for StatusViewData {
+    fn from(value: StatusFromDb) -> Self {
+        // only vouch for the handle if it resolves back to the author
+        let handle = value
+            .handle
+            .filter(|_| value.handle_verified.is_some_and(|v| v != 0))
+            .and_then(|h| Handle::new(h).ok());
+
+        Self {
+            author: ActorViewData {
+                did: value.author_did,
+                handle,
+            }
+            .into(),
+            created_at: Datetime::new(value.created_at.fixed_offset()),
+            expires_at: value.expires_at.map(|t| Datetime::new(t.fixed_offset())),
+            indexed_at: Datetime::new(value.indexed_at.fixed_offset()),
+            status: value.status,
+            text: value.text,
+            uri: value.uri,
+            url: value.url,
+        }
+    }
+}
+
+impl StatusWithHandle {
+    pub fn set_handle(&mut self, handle: Option<ActorHandle>) {
+        self.handle_verified = handle.as_ref().is_some_and(|h| h.verified);
+        self.handle = handle.map(|h| format!("@{}", h.handle.as_str()));
+    }
+}
+
 // impl From<Status> for StatusWithHandle {
 //     fn from(value: Status) -> Self {
 //         Self {
@@ -122,3 +299,49 @@ pub const STATUS_OPTIONS: [&str; 30] = [
     "🪞",
     "✨",
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::types::string::Datetime;
+
+    fn record(status: &str, text: Option<&str>) -> status::RecordData {
+        status::RecordData {
+            created_at: Datetime::now(),
+            expires_at: None,
+            status: status.to_string(),
+            text: text.map(str::to_string),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn record_limits_count_graphemes() {
+        // one grapheme, several code points
+        assert!(check_record_limits(&record("👩‍👩‍👧", None)).is_ok());
+        assert!(check_record_limits(&record("👍👍", None)).is_err());
+        assert!(check_record_limits(&record("", None)).is_err());
+
+        let family_text = "👩‍👩‍👧".repeat(40);
+        assert!(check_record_limits(&record("👍", Some(&family_text))).is_ok());
+        let long_text = "a".repeat(101);
+        assert!(check_record_limits(&record("👍", Some(&long_text))).is_err());
+    }
+
+    #[test]
+    fn record_limits_cap_bytes() {
+        // 1 grapheme, but far over maxLength
+        let zalgo = format!("a{}", "\u{0301}".repeat(600));
+        assert!(check_record_limits(&record("👍", Some(&zalgo))).is_err());
+        assert!(check_record_limits(&record(&zalgo, None)).is_err());
+    }
+
+    #[test]
+    fn every_offered_status_is_within_limits() {
+        // what the status form posts is checked with these too
+        for status in STATUS_OPTIONS {
+            assert!(check_status_limits(status).is_ok(), "{status}");
+        }
+        assert!(check_status_limits("not an emoji").is_err());
+    }
+}
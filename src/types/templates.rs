@@ -13,12 +13,31 @@ pub struct HomeTemplate {
     pub status_options: &'static [&'static str],
     pub profile: Option<Profile>,
     pub my_status: Option<status::RecordData>,
+    /// at:// uri of `my_status`, what "undo" deletes
+    pub my_status_uri: Option<String>,
     pub recent_statuses: Vec<StatusWithHandle>,
 }
 
 impl HomeTemplate {
     pub fn recent_statuses_json(&self) -> String {
-        serde_json::to_string(&self.recent_statuses).unwrap_or_else(|_| "[]".to_string())
+        script_json(&self.recent_statuses, "[]")
+    }
+
+    pub fn my_status_uri_json(&self) -> String {
+        script_json(&self.my_status_uri, "null")
+    }
+}
+
+/// JSON for inlining into a `<script>` block. Status text comes from anyone's repo, and a
+/// literal `</script>` (or `<!--`) in it would end the block early, so the characters HTML
+/// cares about are escaped. The result is the same JSON once parsed.
+fn script_json<T: Serialize>(value: &T, fallback: &str) -> String {
+    match serde_json::to_string(value) {
+        Ok(json) => json
+            .replace('&', "\\u0026")
+            .replace('<', "\\u003c")
+            .replace('>', "\\u003e"),
+        Err(_) => fallback.to_string(),
     }
 }
 
@@ -30,8 +49,90 @@ impl IntoResponse for HomeTemplate {
     }
 }
 
+/// The login form again, with why the last attempt failed
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate {
+    pub error: String,
+    /// prefilled into the form so retrying is one click
+    pub identifier: String,
+}
+
+/// Shown instead of the home page when a session lacks scopes the app now asks for
+#[derive(Template)]
+#[template(path = "reconsent.html")]
+pub struct ReconsentTemplate {
+    pub missing: Vec<String>,
+}
+
+impl IntoResponse for ReconsentTemplate {
+    fn into_response(self) -> axum::response::Response {
+        let html = self.render().expect("template should be valid");
+
+        Html::from(html).into_response()
+    }
+}
+
+/// One author's recent statuses, what cross-posts link back to
+#[derive(Template)]
+#[template(path = "profile.html")]
+pub struct ProfileTemplate {
+    pub did: String,
+    /// verified handle, with the leading @
+    pub handle: Option<String>,
+    pub statuses: Vec<StatusWithHandle>,
+}
+
+impl IntoResponse for ProfileTemplate {
+    fn into_response(self) -> axum::response::Response {
+        let html = self.render().expect("template should be valid");
+
+        Html::from(html).into_response()
+    }
+}
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsTemplate {
+    pub sessions: Vec<SessionView>,
+}
+
+impl IntoResponse for SessionsTemplate {
+    fn into_response(self) -> axum::response::Response {
+        let html = self.render().expect("template should be valid");
+
+        Html::from(html).into_response()
+    }
+}
+
+/// An active session as shown on the sessions page
+pub struct SessionView {
+    pub handle: String,
+    pub created_at: String,
+    pub last_seen: String,
+    pub user_agent: String,
+    /// whether this is the session making the request
+    pub current: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Profile {
     pub did: String,
     pub display_name: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_json_cannot_close_the_script_block() {
+        let json = script_json(&vec!["</script><script>alert(1)</script> & <!--"], "[]");
+
+        assert!(!json.contains('<'));
+        assert!(!json.contains('>'));
+        assert!(!json.contains('&'));
+        let parsed: Vec<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, vec!["</script><script>alert(1)</script> & <!--"]);
+    }
+}